@@ -1,45 +1,23 @@
 extern crate egui;
 extern crate epi;
 
+mod diagnostics;
+mod groups;
+mod history;
+mod keyframes;
+mod scene;
+mod timeline;
 mod vertslide;
-#[derive(Debug, Copy, Clone)]
-struct Vertex {
-    used: bool,
-    just_released: bool,
-    selected: bool,
-    
-    x: f64,
-    y: f64,
-    last_x: f64,
-    last_y: f64, 
-    momentum_x: f64,
-    momentum_y: f64,
-    momentum_c: f64,
-    radius: u32, //should it be i64?
-    wheel: bool,
-    heading: f64,
-    phase: u8
-}
-#[derive(Debug, Copy, Clone)]
-struct Link {
-    //used: bool,
-    src: usize,
-    dest: usize,
-    length: f64,
-    tension: f64,
-    push_timing: i32,
-    push_span: i32,
-    push_strength: f64 ,
-    push: f64, 
-    last_len: f64,
-    mid_x : f64,
-    mid_y : f64,
-    phase : u8
-}
+pub use crate::world::Presets;
+use crate::world::{Collisions, Integrator, LinkKind, MotorKind, Obstacle, ParseError, Player, Recorder, Replay, Waveform, World, DEFAULT_BREAK_STRAIN, MAX_CYCLE_LENGTH, MIN_CYCLE_LENGTH};
+use serde::{Deserialize, Serialize};
+#[derive(Clone, Serialize, Deserialize)]
 struct DisplayOptions {
     show_link_handles: bool,
     show_links: bool,
     show_vertices: bool,
+    /// Draw vertex dots bigger the heavier they are.
+    show_mass: bool,
     show_wheels: bool,
     num_wheel_spokes: u8,
     shade_wheels: bool,
@@ -58,34 +36,13 @@ struct DisplayOptions {
     wheel_shade_color:Color32,
     wheel_spoke_color:Color32,
     hover_color:Color32,
-}
-struct Environment {
-    gravity : f64,
-    atmosphere : f64,
-    wall_bounce : f64,
-    wall_friction : f64,
-    left_wind : f64,
-    tension : f64,
-    clock_speed : i32,
-}
-struct Walls{
-    ceiling: bool,
-    floor: bool,
-    left: bool,
-    right: bool,
+    terrain_color: Color32,
 }
 pub struct State {
     display_options: DisplayOptions,
-    environment: Environment,
-    triangles_updated: bool,
-    triangles: std::collections::HashSet<(usize,usize,usize)>,
-    walls: Walls,
-    vertices: Vec<Vertex>,
-    links: Vec<Link>,
+    world: World,
     mouse_x: f64,
     mouse_y: f64,
-    cycle_time: i32,
-    clock_pause: bool,
     mode: u8, //sum type
     sub_mode: u8, //sum type
     sub_mode_data: usize, // todo: improve (use a sum type)
@@ -94,105 +51,268 @@ pub struct State {
     hover_vertex: Option<usize>,
     hover_link: Option<usize>,
     drag_dot: Option<usize>,
+    sel_obstacle: Option<usize>,
+    hover_point: Option<(usize, usize)>, // obstacle, point
+    drag_point: Option<(usize, usize)>,
+    drag_key: Option<usize>, // keyframe of sel_link, in the Muscles window
+    sel_muscles: Vec<usize>, // links whose tracks are chosen in the Muscles window
+    track_drag: Option<timeline::TrackDrag>,
     width: u32,
     height: u32,
-    rightwall: f64,
-    ceiling: f64,
-    auto_reverse_cycle: i32, 
-    auto_reverse_enabled: bool,
     current_phase: u8, //sum type?
+    ticks_per_second: f64,
+    max_catch_up: u32,
+    tick_accumulator: f64,
+    last_frame_time: Option<f64>,
+    file_path: Option<std::path::PathBuf>,
+    recent_files: Vec<std::path::PathBuf>,
+    load_error: Option<String>,
+    unsaved_changes: bool,
+    /// A new scene waiting on the user to agree to lose their unsaved changes.
+    confirm_replace: Option<Replace>,
+    history: history::History,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    replay: Option<Replay>,
+    /// Ticks simulated since the scene was loaded, to date the entries in `break_log`.
+    ticks_run: u64,
+    break_log: Vec<String>,
+    snaps: Vec<Snap>,
+    show_diagnostics: bool,
+    diagnostics: diagnostics::DiagnosticsLog,
+}
+/// Where a link just broke, drawn as a little burst for a few frames.
+struct Snap {
+    x: f64,
+    y: f64,
+    age: u32,
 }
+/// Ways the scene can be replaced, which have to ask first if it has unsaved changes.
+enum Replace {
+    Preset(Presets),
+    #[cfg(not(target_arch = "wasm32"))]
+    OpenDialog,
+    #[cfg(not(target_arch = "wasm32"))]
+    Open(std::path::PathBuf),
+}
+/// How many frames a snap stays on screen, and how many breaks the log keeps.
+const SNAP_FRAMES: u32 = 15;
+const MAX_BREAK_LOG: usize = 100;
 use egui::color::Color32;
 
-#[derive(Debug, Copy, Clone)]
-pub enum Presets {
-    Walker, Unicycle, Jumper, Spikeball, Muscles, Dancer, AntiGrav, Blank
-}
 impl State {
-    pub fn load_presets(&mut self, preset:Presets) {
-        match preset {
-            Presets::Blank => self.legacy_parse(include_str!("../blank.botz")).unwrap(),
-            Presets::Walker => self.legacy_parse(include_str!("../walker.botz")).unwrap(),
-            Presets::Unicycle => self.legacy_parse(include_str!("../unicycle.botz")).unwrap(),
-            Presets::Jumper => self.legacy_parse(include_str!("../jumper.botz")).unwrap(),
-            Presets::Spikeball => self.legacy_parse(include_str!("../spikeball.botz")).unwrap(),
-            Presets::Muscles => self.legacy_parse(include_str!("../muscles.botz")).unwrap(),
-            Presets::Dancer => self.legacy_parse(include_str!("../dancer.botz")).unwrap(),
-            Presets::AntiGrav => self.legacy_parse(include_str!("../antigrav.botz")).unwrap(),
+    pub fn load_presets(&mut self, preset:Presets) -> Result<(), ParseError> {
+        self.legacy_parse(preset.source())?;
+        self.file_path = None;
+        self.unsaved_changes = false;
+        Ok(())
+    }
+    pub fn legacy_parse(&mut self,file:&str) -> Result<(), ParseError> {
+        self.mode = self.world.legacy_parse(file)?;
+        self.scene_replaced();
+        Ok(())
+    }
+    fn scene_replaced(&mut self) {
+        self.stop_recording();
+        self.player = None;
+        self.drop_selection();
+        self.history.clear(&self.world);
+        self.ticks_run = 0;
+        self.break_log.clear();
+        self.snaps.clear();
+        self.diagnostics.clear();
+    }
+    /// Bookkeeping after each tick: logs the links that snapped and starts their snap
+    /// effects, and samples the diagnostics if they're being watched.
+    fn ticked(&mut self) {
+        self.ticks_run += 1;
+        if self.show_diagnostics { self.diagnostics.record(self.ticks_run, &self.world) }
+        if self.world.broken.is_empty() { return }
+        for broken in &self.world.broken {
+            let (src, dest) = (self.world.vertices[broken.link.src], self.world.vertices[broken.link.dest]);
+            self.snaps.push(Snap { x: (src.x + dest.x) / 2.0, y: (src.y + dest.y) / 2.0, age: 0 });
+            let how = if broken.strain > 0.0 { "stretched" } else { "squashed" };
+            self.break_log.push(format!("Tick {}: link {} {} {:.0}% and snapped", self.ticks_run, broken.index, how, broken.strain.abs() * 100.0));
         }
+        let excess = self.break_log.len().saturating_sub(MAX_BREAK_LOG);
+        self.break_log.drain(..excess);
+        // the links after a broken one have all moved down
+        self.sel_link = None;
+        self.sel_muscles.clear();
+        self.track_drag = None;
+        self.hover_link = None;
     }
-    pub fn legacy_parse(&mut self,file:&str) -> Option<()> {
-        self.vertices = Vec::new();
-        self.links = Vec::new();
+    fn drop_selection(&mut self) {
+        self.sel_obstacle = None;
+        self.hover_point = None;
+        self.drag_point = None;
+        self.drag_key = None;
+        self.sel_muscles.clear();
+        self.track_drag = None;
         self.sel_link = None;
         self.sel_vertex = None;
         self.drag_dot = None;
         self.sub_mode = 0;
-        self.triangles_updated = true;
-        self.mode = 0;
-        let records = file.split(";");
-        for i in records {
-            if let Some(c) = i.chars().nth(0) {
-                if c == 'G' { self.environment.gravity = i[1..].parse().ok()? }
-                if c == 'A' { self.environment.atmosphere = i[1..].parse().ok()? }
-                if c == 'F' { self.environment.wall_friction = i[1..].parse().ok()? }
-                if c == 'B' { self.environment.wall_bounce = i[1..].parse().ok()? }
-                if c == 'W' { self.environment.left_wind = i[1..].parse().ok()? }
-                if c == 'T' { self.environment.tension = i[1..].parse().ok()? }
-                if c == 'C' { self.environment.clock_speed = i[1..].parse().ok()? }
-                if c == 'M' { self.mode = i[1..].parse().ok()? }
-                if c == 'V' {
-                    let mut vertex = Vertex { x:0.0,y:0.0,heading: 0.0, just_released: false, last_x: 0.0, last_y: 0.0, momentum_c: 0.0, momentum_x: 0.0, momentum_y: 0.0, phase:0, radius:0,selected:false,used:true,wheel:false};
-                    let subrecords = i[1..].split("|");
-                    for j in subrecords {
-                        if let Some(c) = j.chars().nth(0) {
-                            if c == 'X' { vertex.x = j[1..].parse().ok()? };
-                            if c == 'Y' { vertex.y = j[1..].parse().ok()? };
-                            if c == 'H' { vertex.momentum_x = j[1..].parse().ok()? };
-                            if c == 'U' { vertex.momentum_y = j[1..].parse().ok()? };
-                            if c == 'R' { vertex.radius = j[1..].parse().ok()?; vertex.wheel = vertex.radius > 0; };
-                            if c == 'C' { vertex.momentum_c = j[1..].parse().ok()? };
-                            if c == 'P' { vertex.phase = j[1..].parse().ok()? };
-                        }
-                    }
-                    self.vertices.push(vertex)
-                }
-                if c == 'L' {
-                    let mut link = Link { src: 0, dest: 0, last_len: 0.0,length:0.0,mid_x:0.0,mid_y:0.0,phase:0,push:0.0,push_span:0,push_strength:0.0,push_timing:0,tension:0.9};
-                    let subrecords = i[1..].split("|");
-                    for j in subrecords {
-                        if let Some(c) = j.chars().nth(0) {
-                            if c == 'A' { link.src = j[1..].parse().ok()?; link.src -= 1; };
-                            if c == 'B' { link.dest = j[1..].parse().ok()?; link.dest -= 1; };
-                            if c == 'L' { link.length = j[1..].parse().ok()? };
-                            if c == 'T' { link.tension = j[1..].parse().ok()? };
-                            if c == 'S' { link.push_span = j[1..].parse().ok()? };
-                            if c == 'P' { link.push = j[1..].parse().ok()? };
-                            if c == 'N' { link.push_strength = j[1..].parse().ok()? };
-                            if c == 'E' { link.last_len = j[1..].parse().ok()? };
-                            if c == 'M' { link.push_timing = j[1..].parse().ok()? };
-                            //if c == 'P' { link.phase = j[1..].parse().ok()? };
-                        }
-                    }
-                    self.links.push(link)
-                }
-            }
+    }
+    pub fn legacy_serialize(&self) -> String {
+        self.world.legacy_serialize(self.mode)
+    }
+    /// Does `replace`, unless there are unsaved changes, in which case it waits for the
+    /// user to say they can go.
+    fn replace_scene(&mut self, replace: Replace) {
+        if self.unsaved_changes { self.confirm_replace = Some(replace) } else { self.do_replace(replace) }
+    }
+    fn do_replace(&mut self, replace: Replace) {
+        match replace {
+            Replace::Preset(preset) => if let Err(e) = self.load_presets(preset) {
+                self.load_error = Some(format!("The {:?} preset is damaged.\n\nAt {}", preset, e));
+            },
+            #[cfg(not(target_arch = "wasm32"))]
+            Replace::OpenDialog => self.open_dialog(),
+            #[cfg(not(target_arch = "wasm32"))]
+            Replace::Open(path) => self.open_file(path),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_file(&mut self, path: std::path::PathBuf) {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => { self.load_error = Some(format!("Could not read {}: {}", path.display(), e)); return }
+        };
+        if let Err(e) = self.scene_parse(&text) {
+            self.load_error = Some(format!("{} is not a valid Botz scene.\n\n{}", path.display(), e));
+            return
+        }
+        self.load_error = None;
+        self.unsaved_changes = false;
+        self.remember_recent(&path);
+        self.file_path = Some(path);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_file(&mut self, path: std::path::PathBuf) {
+        let legacy = path.extension().map_or(false, |e| e.eq_ignore_ascii_case("botz"));
+        let text = if legacy { self.legacy_serialize() } else { self.scene_serialize() };
+        if let Err(e) = std::fs::write(&path, text) {
+            self.load_error = Some(format!("Could not write {}: {}", path.display(), e));
+            return
+        }
+        let lost = if legacy { self.world.legacy_losses() } else { Vec::new() };
+        if lost.is_empty() {
+            self.load_error = None;
+            self.unsaved_changes = false;
+        } else {
+            // what was left out is still only in the editor, so it still counts as unsaved
+            self.load_error = Some(format!("Saved {}, but the .botz format can't hold {}, so they were left out. Save as .json to keep them.", path.display(), lost.join(", ")));
+        }
+        self.remember_recent(&path);
+        self.file_path = Some(path);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_dialog(&mut self) {
+        let dialog = native_dialog::FileDialog::new().add_filter("Botz scene", &["json", "botz"]);
+        match dialog.show_open_single_file() {
+            Ok(Some(path)) => self.open_file(path),
+            Ok(None) => {},
+            Err(e) => self.load_error = Some(format!("Could not show file dialog: {}", e)),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_as_dialog(&mut self) {
+        let mut dialog = native_dialog::FileDialog::new().add_filter("Botz scene", &["json"]).add_filter("Legacy Botz creature", &["botz"]);
+        if let Some(name) = self.file_path.as_ref().and_then(|p| p.file_name()).and_then(|n| n.to_str()) {
+            dialog = dialog.set_filename(name);
+        }
+        match dialog.show_save_single_file() {
+            Ok(Some(path)) => self.save_file(path),
+            Ok(None) => {},
+            Err(e) => self.load_error = Some(format!("Could not show file dialog: {}", e)),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_current(&mut self) {
+        match self.file_path.clone() {
+            Some(path) => self.save_file(path),
+            None => self.save_as_dialog(),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn remember_recent(&mut self, path: &std::path::Path) {
+        self.recent_files.retain(|p| p != path);
+        self.recent_files.insert(0, path.to_path_buf());
+        self.recent_files.truncate(8);
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn title(&self) -> String {
+        let name = match &self.file_path {
+            Some(path) => path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            None => "Untitled".to_string(),
+        };
+        if self.unsaved_changes { format!("{}*", name) } else { name }
+    }
+    fn set_mode(&mut self, mode: u8) {
+        if mode == 1 { self.history.note("Simulate") }
+        self.mode = mode;
+        self.sub_mode = 0;
+        self.clear_multi_select();
+        self.sel_vertex = None;
+        self.sel_link = None;
+        self.sel_muscles.clear();
+        self.track_drag = None;
+    }
+    fn start_recording(&mut self) {
+        self.player = None;
+        self.recorder = Some(Recorder::start(&self.world));
+        self.set_mode(1);
+    }
+    fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            self.replay = Some(recorder.finish());
+        }
+    }
+    fn start_playback(&mut self, replay: Replay) {
+        self.stop_recording();
+        self.drop_selection();
+        self.set_mode(1);
+        self.player = Some(Player::start(replay, &mut self.world));
+        self.unsaved_changes = true;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn open_replay_dialog(&mut self) {
+        let path = match native_dialog::FileDialog::new().add_filter("Botz replay", &["json"]).show_open_single_file() {
+            Ok(Some(path)) => path,
+            Ok(None) => return,
+            Err(e) => { self.load_error = Some(format!("Could not show file dialog: {}", e)); return }
+        };
+        let replay = std::fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))
+            .and_then(|text| Replay::from_json(&text).map_err(|e| format!("{} is not a valid Botz replay.\n\n{}", path.display(), e)));
+        match replay {
+            Ok(replay) => { self.replay = Some(replay.clone()); self.start_playback(replay) }
+            Err(e) => self.load_error = Some(e),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_replay_dialog(&mut self) {
+        let replay = match &self.replay { Some(replay) => replay.to_json(), None => return };
+        match native_dialog::FileDialog::new().add_filter("Botz replay", &["json"]).show_save_single_file() {
+            Ok(Some(path)) => if let Err(e) = std::fs::write(&path, replay) {
+                self.load_error = Some(format!("Could not write {}: {}", path.display(), e));
+            },
+            Ok(None) => {},
+            Err(e) => self.load_error = Some(format!("Could not show file dialog: {}", e)),
         }
-        
-        Some(())
-        
-
     }
     fn mouse_up(&mut self, button2: bool) {
         let howmany = self.how_many_selected();
         if  !button2 { 
             if self.mode == 0 && self.sub_mode == 2 && howmany == 1 { self.sub_mode = 1 };
             if self.mode == 0 && self.sub_mode == 2 && howmany > 1 { self.sub_mode = 0 };
-            if self.mode == 1 && self.sub_mode == 2 { self.sub_mode = 0; self.vertices[self.sub_mode_data].just_released = true };
+            if self.mode == 1 && self.sub_mode == 2 { self.sub_mode = 0; self.world.vertices[self.sub_mode_data].just_released = true };
         }
         self.drag_dot = None;
+        self.drag_point = None;
     }
     fn mouse_down(&mut self, button2 : bool, shift: bool) {
+        if self.player.is_some() { return }
         if button2 {
             self.sub_mode = 0;
             self.clear_multi_select();
@@ -205,23 +325,31 @@ impl State {
             self.clear_multi_select();
             self.sel_vertex = self.hover_vertex;
             if let Some(i) = self.sel_vertex {
-                self.vertices[i].selected = true;
+                self.world.vertices[i].selected = true;
                 self.sub_mode_data = i;
                 self.sub_mode = 2;
                 self.drag_dot = Some(i);
                 self.sel_link = None;
             }
         } else if self.mode == 0 {
+            if let (Some(point), None, None, false) = (self.hover_point, self.hover_vertex, self.hover_link, self.sub_mode == 1) {
+                self.clear_multi_select();
+                self.sel_vertex = None;
+                self.sel_link = None;
+                self.sel_obstacle = Some(point.0);
+                self.drag_point = Some(point);
+                return
+            }
             if !shift {
                 if self.sub_mode == 1 { // continuing a shape?
                     if self.hover_link == None {
                         if self.hover_vertex == None {
                             self.clear_multi_select();
-                            let inty = self.add_vertex(self.mouse_x, self.mouse_y, 0.0, 0.0, 0, 0.0, self.current_phase);
+                            let inty = self.add_vertex_at_mouse();
                             let _ = self.add_link(inty, self.sub_mode_data);
                             self.sub_mode_data = inty;
                             self.sel_vertex = Some(inty);
-                            self.vertices[inty].selected = true;
+                            self.world.vertices[inty].selected = true;
                             return
                         } else if let Some(inty) = self.hover_vertex {
                             
@@ -230,31 +358,31 @@ impl State {
                                 self.sub_mode = 2;
                                 self.sub_mode_data = inty;
                                 self.sel_vertex = Some(inty);
-                                self.vertices[inty].selected = true;
+                                self.world.vertices[inty].selected = true;
                                 self.drag_dot = Some(inty);
                                 return
                             };
                             self.sub_mode_data = inty;
                             self.sel_vertex = Some(inty);
-                            self.vertices[inty].selected = true;
+                            self.world.vertices[inty].selected = true;
                             return
                         }
                     }
                 } else if self.sub_mode == 0 &&  //starting a shape?
                      self.hover_link.is_none() && self.hover_vertex.is_none() {
                         self.clear_multi_select();
-                        let inty = self.add_vertex(self.mouse_x, self.mouse_y, 0.0, 0.0, 0, 0.0, self.current_phase);
+                        let inty = self.add_vertex_at_mouse();
                         self.sub_mode_data = inty;
                         self.sub_mode = 1;
                         self.sel_vertex = Some(inty);
-                        self.vertices[inty].selected = true;
+                        self.world.vertices[inty].selected = true;
                         return
                     
                 } else 
                 if let Some(applies) = self.hover_vertex {
                     self.clear_multi_select();
                     self.sel_vertex = Some(applies);
-                    self.vertices[applies].selected = true;
+                    self.world.vertices[applies].selected = true;
                     self.sub_mode_data = applies;
                     self.sub_mode = 2;
                     self.drag_dot = Some(applies);
@@ -270,7 +398,7 @@ impl State {
         } else {
             if let Some(applies) = self.hover_vertex {
                 self.sel_vertex = Some(applies);
-                self.vertices[applies].selected = true;
+                self.world.vertices[applies].selected = true;
                 self.sub_mode_data = applies;
                 self.sub_mode = 2;
                 self.drag_dot = Some(applies);
@@ -289,22 +417,38 @@ impl State {
         self.mouse_x = x  as f64  / if self.display_options.zoom2x {2.0} else {1.0};
         self.mouse_y = (self.height as f64 - y as f64)  / if self.display_options.zoom2x {2.0} else {1.0} ;
         if self.sub_mode == 2 {
-            self.vertices[self.sub_mode_data].x = self.mouse_x;
-            self.vertices[self.sub_mode_data].y = self.mouse_y;
+            self.world.vertices[self.sub_mode_data].x = self.mouse_x;
+            self.world.vertices[self.sub_mode_data].y = self.mouse_y;
+            if self.mode == 0 { self.edited("Move vertex") }
+        }
+        if let Some((o, p)) = self.drag_point {
+            self.world.terrain[o].points[p] = (self.mouse_x, self.mouse_y);
+            self.edited("Move terrain");
         }
-        for i in 0..self.vertices.len() {
-            if self.vertices[i].used {
-                if self.mouse_x > (self.vertices[i].x - 12.0) && self.mouse_x < (self.vertices[i].x + 12.0) {
-                    if self.mouse_y > (self.vertices[i].y - 12.0) && self.mouse_y < (self.vertices[i].y + 12.0) {
+        self.hover_point = None;
+        if self.mode == 0 {
+            for (o, obstacle) in self.world.terrain.iter().enumerate() {
+                for (p, &(x, y)) in obstacle.points.iter().enumerate() {
+                    if (self.mouse_x - x).abs() < 8.0 && (self.mouse_y - y).abs() < 8.0 {
+                        self.hover_point = Some((o, p));
+                    }
+                }
+            }
+        }
+        for i in 0..self.world.vertices.len() {
+            if self.world.vertices[i].used {
+                if self.mouse_x > (self.world.vertices[i].x - 12.0) && self.mouse_x < (self.world.vertices[i].x + 12.0) {
+                    if self.mouse_y > (self.world.vertices[i].y - 12.0) && self.mouse_y < (self.world.vertices[i].y + 12.0) {
                         self.hover_vertex = Some(i);
                         self.hover_link = None;
                         return
                     }   
                 }
             }
-            for i in 0..self.links.len() {
-                if self.mouse_x > (self.links[i].mid_x - 12.0) && self.mouse_x < (self.links[i].mid_x + 12.0) {
-                    if self.mouse_y > (self.links[i].mid_y - 12.0) && self.mouse_y < (self.links[i].mid_y + 12.0) {
+            for i in 0..self.world.links.len() {
+                let (mid_x, mid_y) = self.world.link_midpoint(i);
+                if self.mouse_x > (mid_x - 12.0) && self.mouse_x < (mid_x + 12.0) {
+                    if self.mouse_y > (mid_y - 12.0) && self.mouse_y < (mid_y + 12.0) {
                         self.hover_vertex = None;
                         self.hover_link = Some(i);
                         return
@@ -315,109 +459,45 @@ impl State {
             self.hover_link = None;
         }
     }
-    fn cycle_physics(&mut self) {
-        let cycle_size = 200;
-        if !self.clock_pause  {
-            self.cycle_time += self.environment.clock_speed;
-            while self.cycle_time > cycle_size { self.cycle_time -= cycle_size };
-            while self.cycle_time < 0  { self.cycle_time += cycle_size}
+    /// Runs as many physics ticks as the wall-clock time since the last frame calls for, so
+    /// creatures move at the same speed whatever the refresh rate. If we fall further behind
+    /// than `max_catch_up` ticks in one frame the rest of the backlog is dropped.
+    fn advance_simulation(&mut self, now: f64) {
+        let elapsed = self.last_frame_time.map_or(0.0, |last| (now - last).max(0.0));
+        self.last_frame_time = Some(now);
+        if self.mode != 1 {
+            self.tick_accumulator = 0.0;
+            return
         }
-        for i in 0..self.links.len() {
-            let link = &mut self.links[i];
-            link.push = 0.0;
-            if self.cycle_time >= link.push_timing - link.push_span && self.cycle_time < link.push_timing + link.push_span {
-                link.push = link.push_strength * (1.0 - ((link.push_timing - self.cycle_time).abs() as f64 / (link.push_span as f64)));
-                link.push = (link.push / 30.0) * link.length;
-            }
-            if link.push_timing + link.push_span > cycle_size && self.cycle_time < link.push_timing + link.push_span - cycle_size {
-                let temp = link.push_timing - cycle_size;
-                link.push = link.push_strength * (1.0 - (((temp - self.cycle_time).abs() as f64 / link.push_span as f64)));
-                link.push = (link.push / 30.0) * link.length;
-            }
-            if link.push_timing - link.push_span < 0 && self.cycle_time > link.push_timing - link.push_span + cycle_size {
-                let temp = link.push_timing + cycle_size;
-                link.push = link.push_strength * (1.0 - (((temp - self.cycle_time).abs() as f64 / link.push_span as f64)));
-                link.push = (link.push / 30.0) * link.length;
-            }
-            let length_total = if self.clock_pause { link.length } else { link.length + link.push };
-            let t1 = self.vertices[link.src] ;
-            let t2 = self.vertices[link.dest];
-            let xer = (t2.x + t2.momentum_x) - (t1.x + t1.momentum_x);
-            let yer = (t2.y + t2.momentum_y) - (t1.y + t1.momentum_y);
-            let leng = (xer * xer + yer * yer).abs().sqrt();
-            let leng2go_x = ((leng - length_total) / leng) * xer;
-            let leng2go_y = ((leng - length_total) / leng) * yer;
-            { 
-                let t1 = & mut self.vertices[link.src];
-                t1.momentum_x = t1.momentum_x + (leng2go_x / 2.0) * link.tension;
-                t1.momentum_y = t1.momentum_y + (leng2go_y / 2.0) * link.tension;
-            }
-            {
-                let t2 = & mut self.vertices[link.dest];
-                t2.momentum_x = t2.momentum_x + (leng2go_x / 2.0) * -1.0 * link.tension;
-                t2.momentum_y = t2.momentum_y + (leng2go_y / 2.0) * -1.0 * link.tension;
-            }
+        self.tick_accumulator += elapsed * self.ticks_per_second;
+        let mut ticks = 0;
+        while self.tick_accumulator >= 1.0 && ticks < self.max_catch_up {
+            self.cycle_physics();
+            self.tick_accumulator -= 1.0;
+            ticks += 1;
         }
-        for i in 0..self.vertices.len() {
-            let vertex = &mut self.vertices[i];
-            if !vertex.used { continue; }
-            vertex.momentum_y -= self.environment.gravity * 1.5;
-            if vertex.just_released { vertex.momentum_x = 0.0; vertex.momentum_y = 0.0; vertex.just_released = false }
-            vertex.momentum_x += self.environment.left_wind / 10.0;
-            vertex.momentum_x *= 1.0 - self.environment.atmosphere;
-            vertex.momentum_y *= 1.0 - self.environment.atmosphere;
-            if self.drag_dot == Some(i) && self.sub_mode == 2 {
-                vertex.momentum_x = 0.0;
-                vertex.momentum_y = 0.0;
-            }
-            vertex.last_x = vertex.x;
-            vertex.last_y = vertex.y;
-            vertex.x += vertex.momentum_x;
-            vertex.y += vertex.momentum_y;
-            // this shouldn't be needed            
-            vertex.wheel = vertex.radius > 0;
-            self.ceiling = (self.height as f64 - 4.0) / if self.display_options.zoom2x {2.0} else {1.0};
-            self.rightwall = (self.width as f64 - 4.0)  / if self.display_options.zoom2x {2.0} else {1.0};
-            let fric = if vertex.wheel { 0.0 } else { self.environment.wall_friction };
-            // TODO: enable options to toggle walls
-            if self.walls.floor && vertex.y - (vertex.radius as f64) < 0.1 { // floor
-                
-                vertex.y = vertex.radius as f64;
-                vertex.momentum_x *= 1.0 - fric;
-                vertex.momentum_y = (vertex.momentum_y * self.environment.wall_bounce) * -1.0;
-                if vertex.wheel { vertex.momentum_c = vertex.momentum_x }
-            }
-            if self.walls.left && vertex.x - (vertex.radius as f64) < 0.1 { // left wall
-                vertex.x = vertex.radius as f64;
-                vertex.momentum_y *= 1.0 - fric;
-                vertex.momentum_x = (vertex.momentum_x * self.environment.wall_bounce) * -1.0;
-                if vertex.wheel { vertex.momentum_c = -vertex.momentum_y }
-                if self.auto_reverse_enabled {
-                    if self.auto_reverse_cycle == 0 { self.auto_reverse_cycle = 2; self.environment.clock_speed *= -1; };
-                    if self.auto_reverse_cycle == 1 { self.auto_reverse_cycle = 2; self.environment.clock_speed *= -1; };
-                }
-            }
-            if self.walls.right && vertex.x + (vertex.radius as f64) > self.rightwall - 0.1 { // right wall
-                vertex.x = self.rightwall - vertex.radius as f64;
-                vertex.momentum_y *= 1.0 - fric;
-                vertex.momentum_x = (vertex.momentum_x * self.environment.wall_bounce) * -1.0;
-                if vertex.wheel { vertex.momentum_c = vertex.momentum_y }
-                if self.auto_reverse_enabled {
-                    if self.auto_reverse_cycle == 0 { self.auto_reverse_cycle = 1; self.environment.clock_speed *= -1; };
-                    if self.auto_reverse_cycle == 2 { self.auto_reverse_cycle = 1; self.environment.clock_speed *= -1; };
+        if self.tick_accumulator >= 1.0 { self.tick_accumulator = self.tick_accumulator.fract() }
+    }
+    fn cycle_physics(&mut self) {
+        if let Some(player) = &mut self.player {
+            if !player.step(&mut self.world) {
+                if player.diverged(&self.world) {
+                    self.load_error = Some("Playback did not end where the recording did. Was it recorded with a different build?".to_string());
                 }
+                self.player = None;
             }
-            if self.walls.ceiling && vertex.y + (vertex.radius as f64) > self.ceiling - 0.1 {
-                vertex.y = self.ceiling - vertex.radius as f64;
-                vertex.momentum_x *= 1.0 - fric;
-                vertex.momentum_y = (vertex.momentum_y * self.environment.wall_bounce) * -1.0;
-                if vertex.wheel { vertex.momentum_c = -vertex.momentum_x }
-            }
-            vertex.heading += vertex.momentum_c;
-            if vertex.heading > 360.0 { vertex.heading -= 360.0 };
-            if vertex.heading < 0.0 { vertex.heading += 360.0 };
+            self.ticked();
+            return
         }
-        
+        let scale = if self.display_options.zoom2x {2.0} else {1.0};
+        self.world.ceiling = (self.height as f64 - 4.0) / scale;
+        self.world.rightwall = (self.width as f64 - 4.0) / scale;
+        self.world.held = if self.sub_mode == 2 { self.drag_dot } else { None };
+        match &mut self.recorder {
+            Some(recorder) => recorder.step(&mut self.world),
+            None => self.world.step(),
+        }
+        self.ticked();
     }
     fn draw_playfield_line(&self,ui: &mut egui::Ui,  rect: &egui::Rect, x: f64, y: f64, tx: f64, ty: f64, color: Color32) {
         if self.display_options.zoom2x {
@@ -426,6 +506,27 @@ impl State {
             ui.painter().line_segment([rect.left_top() + egui::Vec2::new(1.0*x as f32, rect.height() - (1.0 * y) as f32), rect.left_top() + egui::Vec2::new(1.0*tx as f32, rect.height() - (1.0 * ty) as f32)], egui::Stroke::new(1.0,color) );
         }
     }
+    /// Springs are plain lines, ropes dashed, struts thick and rigid links doubled.
+    fn draw_link(&self, ui: &mut egui::Ui, rect: &egui::Rect, (x, y): (f64, f64), (tx, ty): (f64, f64), kind: LinkKind, color: Color32) {
+        let width = if self.display_options.zoom2x { 2.0 } else { 1.0 };
+        let (from, to) = (self.to_playfield(rect, x, y), self.to_playfield(rect, tx, ty));
+        match kind {
+            LinkKind::Spring => self.draw_playfield_line(ui, rect, x, y, tx, ty, color),
+            LinkKind::Rope => {
+                let dashes = ((to - from).length() / (4.0 * width)).ceil().max(1.0) as usize;
+                for i in (0..dashes).step_by(2) {
+                    let (a, b) = (i as f32 / dashes as f32, ((i + 1) as f32 / dashes as f32).min(1.0));
+                    ui.painter().line_segment([from + (to - from) * a, from + (to - from) * b], egui::Stroke::new(width, color));
+                }
+            }
+            LinkKind::Strut => { ui.painter().line_segment([from, to], egui::Stroke::new(3.0 * width, color)); }
+            LinkKind::Rigid => {
+                let offset = (to - from).normalized().rot90() * 1.5 * width;
+                ui.painter().line_segment([from + offset, to + offset], egui::Stroke::new(width, color));
+                ui.painter().line_segment([from - offset, to - offset], egui::Stroke::new(width, color));
+            }
+        }
+    }
     fn draw_playfield_dot(&self,ui: &mut egui::Ui,   rect: &egui::Rect,x: f64, y: f64, color: Color32) {
         if self.display_options.zoom2x {
             ui.painter().circle_filled(rect.left_top() + egui::Vec2::new(2.0*x as f32, rect.height() - (2.0 * y) as f32), 4.0, color);
@@ -464,15 +565,28 @@ impl State {
         return Color32::from_rgba_unmultiplied((r*255.0) as u8, (g*255.0) as u8, (b*255.0) as u8, (a*255.0) as u8);
     }
     fn draw(&mut self, ui: &mut egui::Ui, rect: egui::Rect) {
-        
+        for (o, obstacle) in self.world.terrain.iter().enumerate() {
+            let color = if self.sel_obstacle == Some(o) { self.display_options.selection_color } else { self.display_options.terrain_color };
+            let stroke = egui::Stroke::new(if self.display_options.zoom2x { 6.0 } else { 3.0 }, color);
+            for ((x0, y0), (x1, y1)) in obstacle.segments() {
+                ui.painter().line_segment([self.to_playfield(&rect, x0, y0), self.to_playfield(&rect, x1, y1)], stroke);
+            }
+            if self.mode == 0 {
+                for (p, &(x, y)) in obstacle.points.iter().enumerate() {
+                    if self.hover_point == Some((o, p)) {
+                        self.draw_playfield_circle(ui, &rect, x, y, 4, self.display_options.hover_color);
+                    } else if self.sel_obstacle == Some(o) {
+                        self.draw_playfield_dot(ui, &rect, x, y, self.display_options.selection_color);
+                    }
+                }
+            }
+        }
         if self.display_options.show_links {
-            for i in 0..self.links.len() {
-                let link = &mut self.links[i];
-                let src = self.vertices[link.src];
-                let dest = self.vertices[link.dest];
-                link.mid_x = dest.x + ((src.x - dest.x) / 2.0);
-                link.mid_y = dest.y + ((src.y - dest.y) / 2.0);
-                let link = & self.links[i];
+            for i in 0..self.world.links.len() {
+                let link = &self.world.links[i];
+                let src = self.world.vertices[link.src];
+                let dest = self.world.vertices[link.dest];
+                let (mid_x, mid_y) = self.world.link_midpoint(i);
                 let color1 = if self.sel_link == Some(i) {
                     self.display_options.selection_color
                 } else {
@@ -498,33 +612,64 @@ impl State {
                 } else {
                     self.display_options.link_color
                 };
-                self.draw_playfield_line(ui,&rect,src.x, src.y, dest.x, dest.y,color);
+                self.draw_link(ui, &rect, (src.x, src.y), (dest.x, dest.y), link.kind, color);
                 if self.display_options.show_link_handles {
-                    self.draw_playfield_line( ui,&rect,link.mid_x -5.0, link.mid_y, link.mid_x + 5.0, link.mid_y,color1);
-                    self.draw_playfield_line( ui,&rect,link.mid_x, link.mid_y-5.0, link.mid_x , link.mid_y+ 5.0,color1);
+                    self.draw_playfield_line( ui,&rect,mid_x -5.0, mid_y, mid_x + 5.0, mid_y,color1);
+                    self.draw_playfield_line( ui,&rect,mid_x, mid_y-5.0, mid_x , mid_y+ 5.0,color1);
                 }
                 if self.hover_link == Some(i) {
-                    self.draw_playfield_circle(ui,&rect, link.mid_x, link.mid_y, 4, self.display_options.hover_color);
+                    self.draw_playfield_circle(ui,&rect, mid_x, mid_y, 4, self.display_options.hover_color);
                 } else if self.sel_link == Some(i) {
-                    self.draw_playfield_circle(ui,&rect, link.mid_x, link.mid_y, 4, self.display_options.selection_color);
+                    self.draw_playfield_circle(ui,&rect, mid_x, mid_y, 4, self.display_options.selection_color);
                 }
                 
             }
             if self.mode == 0 && self.sub_mode == 1 {
                 if let Some (i) = self.sel_vertex {
                     if !ui.input().modifiers.shift {
-                        self.draw_playfield_line(ui,&rect, self.vertices[i].x, self.vertices[i].y, self.mouse_x, self.mouse_y, Color32::GRAY);
+                        self.draw_playfield_line(ui,&rect, self.world.vertices[i].x, self.world.vertices[i].y, self.mouse_x, self.mouse_y, Color32::GRAY);
                     }
                 }
             }
         }
         
-        for i in 0..self.vertices.len() {
-            let vertex = & self.vertices[i];
+        for &i in self.diagnostics.bad() {
+            // mark where a vertex that's gone NaN or infinite last was
+            if let Some((x, y)) = self.diagnostics.last_finite(i) {
+                self.draw_playfield_circle(ui, &rect, x, y, 8, Color32::RED);
+                self.draw_playfield_line(ui, &rect, x - 6.0, y - 6.0, x + 6.0, y + 6.0, Color32::RED);
+                self.draw_playfield_line(ui, &rect, x - 6.0, y + 6.0, x + 6.0, y - 6.0, Color32::RED);
+            }
+        }
+        for snap in &self.snaps {
+            // a burst of short rays flying outwards and fading
+            let t = snap.age as f64 / SNAP_FRAMES as f64;
+            let color = Self::mix_colors(self.display_options.link_stretched_color, self.display_options.background_color, t);
+            for ray in 0..6 {
+                let angle = ray as f64 * std::f64::consts::PI / 3.0 + 0.3;
+                let (inner, outer) = (2.0 + snap.age as f64, 6.0 + snap.age as f64);
+                self.draw_playfield_line(ui, &rect, snap.x + angle.cos() * inner, snap.y + angle.sin() * inner, snap.x + angle.cos() * outer, snap.y + angle.sin() * outer, color);
+            }
+        }
+        for snap in &mut self.snaps { snap.age += 1 }
+        self.snaps.retain(|snap| snap.age < SNAP_FRAMES);
+
+        for i in 0..self.world.vertices.len() {
+            let vertex = & self.world.vertices[i];
             if !vertex.used { continue};
-            if self.display_options.show_vertices {
+            if self.display_options.show_vertices && self.display_options.show_mass {
+                let size = if self.display_options.zoom2x { 4.0 } else { 2.0 } * vertex.mass.sqrt().clamp(0.5, 5.0) as f32;
+                ui.painter().circle_filled(self.to_playfield(&rect, vertex.x, vertex.y), size, self.display_options.vertex_color);
+            } else if self.display_options.show_vertices {
                 self.draw_playfield_dot(ui,&rect,vertex.x, vertex.y, self.display_options.vertex_color);
             }
+            if vertex.pinned {
+                // a little anchor triangle underneath
+                let (x, y) = (vertex.x, vertex.y);
+                self.draw_playfield_line(ui, &rect, x, y, x - 4.0, y - 6.0, self.display_options.vertex_color);
+                self.draw_playfield_line(ui, &rect, x, y, x + 4.0, y - 6.0, self.display_options.vertex_color);
+                self.draw_playfield_line(ui, &rect, x - 4.0, y - 6.0, x + 4.0, y - 6.0, self.display_options.vertex_color);
+            }
             if self.hover_vertex == Some(i) {
                 self.draw_playfield_circle( ui,&rect,vertex.x, vertex.y, 4, self.display_options.hover_color);
             }
@@ -533,6 +678,10 @@ impl State {
             }
             if self.display_options.show_wheels && vertex.wheel {
                 self.draw_playfield_circle(ui, &rect,vertex.x, vertex.y, vertex.radius, self.display_options.wheel_color);
+                if vertex.motor.kind != MotorKind::Off {
+                    // a hub marks a motor
+                    self.draw_playfield_circle(ui, &rect, vertex.x, vertex.y, (vertex.radius / 4).max(3), self.display_options.wheel_color);
+                }
                 if self.display_options.shade_wheels {
                     self.draw_playfield_filled_circle(ui, &rect,vertex.x, vertex.y, vertex.radius, self.display_options.wheel_shade_color);
                 }
@@ -548,106 +697,74 @@ impl State {
             }
         }
         if self.display_options.shade_body {
-            self.find_triangles();
+            self.world.find_triangles();
             let mut vec = Vec::new();
-            for (x1,x2,x3) in &self.triangles {
+            for (x1,x2,x3) in self.world.triangles() {
                 let (p1,p2,p3) = (*x1,*x2,*x3);
                 vec.push(egui::Shape::convex_polygon(vec![
-                    self.to_playfield(&rect, self.vertices[p1].x,self.vertices[p1].y),self.to_playfield(&rect, self.vertices[p2].x,self.vertices[p2].y),
-                    self.to_playfield(&rect, self.vertices[p2].x,self.vertices[p2].y),self.to_playfield(&rect, self.vertices[p3].x,self.vertices[p3].y),
-                    self.to_playfield(&rect, self.vertices[p3].x,self.vertices[p3].y),self.to_playfield(&rect, self.vertices[p1].x,self.vertices[p1].y),
+                    self.to_playfield(&rect, self.world.vertices[p1].x,self.world.vertices[p1].y),self.to_playfield(&rect, self.world.vertices[p2].x,self.world.vertices[p2].y),
+                    self.to_playfield(&rect, self.world.vertices[p2].x,self.world.vertices[p2].y),self.to_playfield(&rect, self.world.vertices[p3].x,self.world.vertices[p3].y),
+                    self.to_playfield(&rect, self.world.vertices[p3].x,self.world.vertices[p3].y),self.to_playfield(&rect, self.world.vertices[p1].x,self.world.vertices[p1].y),
                 ], self.display_options.shade_color, egui::Stroke::new(0.0, Color32::BLACK)))
             }
             ui.painter().extend(vec);
         }
     }
-    fn find_triangles(&mut self)   {
-        if !self.triangles_updated {
-            return
-        }
-        let mut adj: Vec<Vec<bool>> = vec![vec![false;self.vertices.len()]; self.vertices.len()];
-        let mut found : std::collections::HashSet<(usize,usize,usize)> = std::collections::HashSet::new();
-        for i in 0..self.links.len() {
-            adj[self.links[i].src][self.links[i].dest] = true;
-            adj[self.links[i].dest][self.links[i].src] = true;
+    /// Marks the scene as changed and names the change for the undo history.
+    fn edited(&mut self, what: &'static str) {
+        self.unsaved_changes = true;
+        self.history.note(what);
+    }
+    fn undo(&mut self) {
+        if self.history.undo(&mut self.world) {
+            self.drop_selection();
+            self.unsaved_changes = true;
         }
-        for link in &self.links {
-            for j in 0..self.vertices.len() {
-                if j != link.src && j != link.dest && adj[link.src][j] && adj[link.dest][j] {
-                    if j < link.src && link.src < link.dest {
-                        found.insert((j,link.src,link.dest));
-                    } else if j < link.dest && link.dest < link.src {
-                        found.insert((j,link.dest,link.src));
-                    } else if link.src < j && j < link.dest {
-                        found.insert((link.src,j,link.dest));
-                    } else if link.dest < j && j < link.src {
-                        found.insert((link.dest,j,link.src));
-                    } else if link.dest < link.src && link.src < j {
-                        found.insert((link.dest,link.src, j));
-                    } else {
-                        found.insert((link.src,link.dest, j));
-                    }
-                }
-            }
+    }
+    fn redo(&mut self) {
+        if self.history.redo(&mut self.world) {
+            self.drop_selection();
+            self.unsaved_changes = true;
         }
-        self.triangles = found;
-        self.triangles_updated = false;
     }
     fn reset_all_links(&mut self) {
-        for i in 0..self.links.len() {
-            self.reset_link(i);
-        }
+        self.world.reset_all_links();
+        self.edited("Reset link lengths");
     }
     fn reset_all_connected_links(&mut self) {
-        for i in 0..self.links.len() {
-            let t = self.links[i].src;
-            if self.vertices[t].used && self.vertices[t].selected {
-                self.reset_link(i);
+        for i in 0..self.world.links.len() {
+            let t = self.world.links[i].src;
+            if self.world.vertices[t].used && self.world.vertices[t].selected {
+                self.world.reset_link(i);
+                self.edited("Reset link lengths");
                 continue;
             }
-            let t = self.links[i].dest;
-            if self.vertices[t].used && self.vertices[t].selected {
-                self.reset_link(i);
+            let t = self.world.links[i].dest;
+            if self.world.vertices[t].used && self.world.vertices[t].selected {
+                self.world.reset_link(i);
+                self.edited("Reset link lengths");
                 continue;
             }
         }
     }
     fn reset_link(&mut self, link: usize) {
-        let t1 = self.vertices[self.links[link].src];
-        let t2 = self.vertices[self.links[link].dest];        
-        let xer = t2.x - t1.x;
-        let yer = t2.y - t1.y;
-        self.links[link].length = ((xer * xer + yer * yer).abs()).sqrt()
+        self.world.reset_link(link);
+        self.edited("Reset link length");
     }
-
     fn set_wheel(&mut self, vertex: usize, radius: u32) {
-        self.vertices[vertex].radius = radius;
-        self.vertices[vertex].wheel = radius > 0;
-        self.vertices[vertex].heading = 0.0;
+        self.world.set_wheel(vertex, radius);
+        self.edited("Add wheel");
     }
-    fn add_vertex(&mut self, x: f64, y: f64, momentum_x: f64, momentum_y: f64, radius: u32, momentum_c: f64, phase: u8) -> usize {
-        
-        let vertex = Vertex {
-            x,y,momentum_c,momentum_x,momentum_y,radius,phase,
-            heading:0.0, wheel: radius > 0, just_released:false, last_x:0.0,last_y:0.0, selected:false,
-            used:true,
-        };
-        for i in 0..self.vertices.len() {
-            if self.vertices[i].used == false {
-                self.vertices[i] = vertex;
-                return i;
-            }
-        }
-        self.triangles_updated = true;
-        self.vertices.push(vertex);
-        self.vertices.len() - 1
+    fn add_vertex_at_mouse(&mut self) -> usize {
+        self.edited("Add vertex");
+        self.world.add_vertex(self.mouse_x, self.mouse_y, 0.0, 0.0, 0, 0.0, self.current_phase)
     }
     fn delete(&mut self) {
         
         if let Some(i) = self.sel_vertex {
             self.delete_vertex(i);
-            for i in 0..self.vertices.len() {
-                if self.vertices[i].selected {
+            for i in 0..self.world.vertices.len() {
+                if self.world.vertices[i].selected {
                     self.delete_vertex(i);
                 }
             }
@@ -657,13 +774,19 @@ impl State {
         if let Some(i) = self.sel_link {
             self.delete_link(i);
         }
+        if let Some(o) = self.sel_obstacle.take() {
+            self.world.terrain.remove(o);
+            self.hover_point = None;
+            self.drag_point = None;
+            self.edited("Delete");
+        }
         self.clear_multi_select();
         self.sel_vertex = None;
         self.sel_link = None;
     }
     fn delete_link(&mut self, id: usize) {
-        self.links.remove(id);
-        self.triangles_updated = true;
+        self.world.delete_link(id);
+        self.edited("Delete");
     }
     fn delete_vertex(&mut self, id: usize) {
         if self.sub_mode_data == id || self.drag_dot == Some(id) {
@@ -671,55 +794,50 @@ impl State {
             self.sub_mode = 0;
             self.drag_dot = None;
         }
-        let mut i = 0;
-        while i != self.links.len() {
-            if  self.links[i].src == id || self.links[i].dest == id {
-                self.links.remove(i);
-            } else {
-                i += 1;
-            }
-        }
-        self.vertices[id].used = false;
-        self.triangles_updated = true;
+        self.world.delete_vertex(id);
+        self.edited("Delete");
+    }
+    /// Puts a new obstacle in the scene and selects it.
+    fn add_obstacle(&mut self, obstacle: Obstacle) {
+        self.world.terrain.push(obstacle);
+        self.drop_selection();
+        self.clear_multi_select();
+        self.sel_vertex = None;
+        self.sel_obstacle = Some(self.world.terrain.len() - 1);
+        self.edited("Add terrain");
     }
     fn how_many_selected(&self) -> usize {
-        self.vertices.iter().filter(|x| x.selected && x.used).count()
+        self.world.vertices.iter().filter(|x| x.selected && x.used).count()
     }
     fn toggle_selection(&mut self, id: usize) {
-        self.vertices[id].selected = !self.vertices[id].selected
+        self.world.vertices[id].selected = !self.world.vertices[id].selected
     }
     fn clear_multi_select(&mut self) {
-        for vertex in &mut self.vertices {
+        for vertex in &mut self.world.vertices {
             vertex.selected = false;
         }
     }
     fn add_link(&mut self, src: usize, dest: usize) -> bool {
-        if src == dest { return false };
-        for link in &self.links {
-            if link.src == src && link.dest == dest { return false };
-            if link.dest == src && link.src == dest { return false };
+        let added = self.world.add_link(src, dest, self.current_phase);
+        if added { self.edited("Add link") }
+        added
+    }
+    /// Keys that work anywhere in the window. While a text box has the keyboard they're left
+    /// to it, so that Backspace in a group's name doesn't delete the selection.
+    fn keyboard_shortcuts(&mut self, ctx: &egui::CtxRef) {
+        if ctx.wants_keyboard_input() { return }
+        let input = ctx.input();
+        if input.modifiers.command && input.key_pressed(egui::Key::Z) {
+            if input.modifiers.shift { self.redo() } else { self.undo() }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            if input.modifiers.command && input.key_pressed(egui::Key::S) { self.save_current() };
+            if input.modifiers.command && input.key_pressed(egui::Key::O) { self.replace_scene(Replace::OpenDialog) };
+        }
+        if input.key_pressed(egui::Key::Backspace) || input.key_pressed(egui::Key::Delete) {
+            self.delete();
         }
-        let x_len = self.vertices[dest].x - self.vertices[src].x;
-        let y_len = self.vertices[dest].y - self.vertices[src].y;
-        let len = (x_len * x_len + y_len * y_len).sqrt();
-        let link = Link {
-            src: src,
-            dest: dest,
-            tension: self.environment.tension,
-            length: len,
-            last_len: 0.0,
-            phase: self.current_phase,
-            push_strength: 0.0,
-            push_timing: 180,
-            push_span: 40,
-            push: 0.0,
-            mid_x: self.vertices[dest].x + (self.vertices[src].x - self.vertices[dest].x) / 2.0,
-            mid_y: self.vertices[dest].y + (self.vertices[src].y - self.vertices[dest].y) / 2.0,
-        };
-        
-        self.links.push(link);
-        self.triangles_updated = true;
-        return true;
     }
 }
 impl epi::App for State {
@@ -731,7 +849,7 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
     let mut vis = egui::Visuals::light();
     vis.window_shadow.extrusion = (vis.window_shadow.extrusion + 1.0) / 8.0;
     ctx.set_visuals(vis);
-    if self.mode == 1 { self.cycle_physics(); };
+    self.advance_simulation(ctx.input().time);
     egui::CentralPanel::default().show(ctx, |ui| {
         let rect = ui.max_rect_finite();
         let (_rect2, response) = ui.allocate_exact_size(rect.size(), egui::Sense::click_and_drag());
@@ -759,26 +877,34 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
         self.draw(ui,rect);
        
     });
+    self.keyboard_shortcuts(ctx);
     egui::Window::new("Menu").title_bar(false).fixed_size(egui::Vec2::new(60.0,100.0))
     .show(ctx, |ui| {
         ui.horizontal(|ui| {
             ui.spacing_mut().item_spacing = egui::vec2(1.0, 2.0);
             if ui.selectable_label(self.mode == 0,"✏️"[0..3].to_string()).on_hover_ui(|ui| {ui.label("Edit");}).clicked() {
-                self.mode = 0;
-                self.sub_mode = 0;
-                self.clear_multi_select();
-                self.sel_vertex = None;
-                self.sel_link = None;
+                self.set_mode(0);
             };
             if ui.selectable_label(self.mode == 1,"▶").on_hover_ui(|ui| {ui.label("Simulate");}).clicked() {
-                self.mode = 1;
-                self.sub_mode = 0;
-                self.clear_multi_select();
-                self.sel_vertex = None;
-                self.sel_link = None;
+                self.set_mode(1);
+            };
+            ui.separator();
+            let next_undo = self.history.next_undo(&self.world);
+            if ui.add(egui::Button::new("⟲").enabled(next_undo.is_some())).on_hover_ui(|ui| {ui.label(format!("Undo {}", next_undo.unwrap_or_default()));}).clicked() {
+                self.undo();
+            };
+            let next_redo = self.history.next_redo(&self.world);
+            if ui.add(egui::Button::new("⟳").enabled(next_redo.is_some())).on_hover_ui(|ui| {ui.label(format!("Redo {}", next_redo.unwrap_or_default()));}).clicked() {
+                self.redo();
+            };
+            ui.separator();
+            if ui.selectable_label(self.show_diagnostics, "📈").on_hover_ui(|ui| {ui.label("Diagnostics");}).clicked() {
+                self.show_diagnostics = !self.show_diagnostics;
             };
         });
         ui.set_width(200.0);
+        #[cfg(not(target_arch = "wasm32"))]
+        ui.label(self.title()).on_hover_text(if self.unsaved_changes { "Has unsaved changes" } else { "No unsaved changes" });
         ui.collapsing("Display Options", |ui| {  egui::Grid::new("poswtable").show(ui, |ui|{
             
             ui.checkbox(&mut self.display_options.show_links, "Links");
@@ -820,6 +946,11 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
             if self.display_options.show_vertices {
                 ui.add_space(4.0);
                 egui::widgets::color_picker::color_edit_button_srgba(ui,&mut self.display_options.vertex_color,egui::widgets::color_picker::Alpha::OnlyBlend);
+                ui.end_row();
+
+                    ui.add_space(8.0);
+                    ui.checkbox(&mut self.display_options.show_mass, "Size by mass");
+                    ui.add_space(-8.0);
             }
             ui.end_row();
             ui.checkbox(&mut self.display_options.show_wheels, "Wheels");
@@ -864,23 +995,132 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
             ui.add_space(4.0);
             egui::widgets::color_picker::color_edit_button_srgba(ui,&mut self.display_options.hover_color,egui::widgets::color_picker::Alpha::OnlyBlend);
             ui.end_row();
+            ui.label("Terrain colour");
+            ui.add_space(4.0);
+            egui::widgets::color_picker::color_edit_button_srgba(ui,&mut self.display_options.terrain_color,egui::widgets::color_picker::Alpha::OnlyBlend);
+            ui.end_row();
             ui.label("Background colour");
             ui.add_space(4.0);
             egui::widgets::color_picker::color_edit_button_srgba(ui,&mut self.display_options.background_color,egui::widgets::color_picker::Alpha::OnlyBlend);
         });});
-        ui.collapsing("Load Preset", |ui| {
-            if ui.button("Blank").clicked() { self.load_presets(Presets::Blank)};
-            if ui.button("Walker").clicked() { self.load_presets(Presets::Walker)};
-            if ui.button("AntiGrav").clicked() { self.load_presets(Presets::AntiGrav)};
-            if ui.button("Dancer").clicked() { self.load_presets(Presets::Dancer)};
-            if ui.button("Unicycle").clicked() { self.load_presets(Presets::Unicycle)};
-            if ui.button("Jumper").clicked() { self.load_presets(Presets::Jumper)};
-            if ui.button("SpikeBall").clicked() { self.load_presets(Presets::Spikeball)};
-            if ui.button("Muscles").clicked() { self.load_presets(Presets::Muscles)};    
+        #[cfg(not(target_arch = "wasm32"))]
+        egui::CollapsingHeader::new("File").id_source("file_menu").show(ui, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Open…").clicked() { self.replace_scene(Replace::OpenDialog) };
+                if ui.button("Save").clicked() { self.save_current() };
+                if ui.button("Save As…").clicked() { self.save_as_dialog() };
+            });
+            if !self.recent_files.is_empty() {
+                ui.label("Recent Files");
+                let mut chosen = None;
+                for path in &self.recent_files {
+                    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                    if ui.button(name).on_hover_text(path.display().to_string()).clicked() {
+                        chosen = Some(path.clone());
+                    }
+                }
+                if let Some(path) = chosen { self.replace_scene(Replace::Open(path)) };
+            }
         });
-        if ui.input().key_pressed(egui::Key::Backspace) || ui.input().key_pressed(egui::Key::Delete) {
-            self.delete();
+        ui.collapsing("Simulation Rate", |ui| { egui::Grid::new("ratetable").show(ui, |ui| {
+            ui.label("Ticks per second");
+            ui.add(egui::DragValue::new(&mut self.ticks_per_second).speed(1).clamp_range(1.0..=600.0));
+            ui.end_row();
+            ui.label("Max catch-up");
+            ui.add(egui::DragValue::new(&mut self.max_catch_up).speed(1).clamp_range(1..=100).suffix(" ticks"));
+        });});
+        ui.collapsing("Replay", |ui| {
+            ui.horizontal(|ui| {
+                if let Some(ticks) = self.recorder.as_ref().map(|r| r.ticks()) {
+                    if ui.button("⏹ Stop").clicked() { self.stop_recording() };
+                    ui.label(format!("Recording, {} ticks", ticks));
+                } else if ui.button("⏺ Record").on_hover_text("Record the simulation from now, including anything you do to it").clicked() {
+                    self.start_recording();
+                }
+            });
+            if let Some(progress) = self.player.as_ref().map(|p| format!("Playing tick {} of {}", p.tick(), p.replay().ticks)) {
+                ui.horizontal(|ui| {
+                    if ui.button("⏹ Stop").clicked() { self.player = None };
+                    ui.label(progress);
+                });
+            } else if let Some(replay) = self.replay.clone() {
+                ui.horizontal(|ui| {
+                    if ui.button("▶ Play").clicked() { self.start_playback(replay.clone()) };
+                    ui.label(format!("{} ticks, {} inputs", replay.ticks, replay.events.len()));
+                });
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            ui.horizontal(|ui| {
+                if ui.button("Open…").clicked() { self.open_replay_dialog() };
+                if ui.add(egui::Button::new("Save…").enabled(self.replay.is_some())).clicked() { self.save_replay_dialog() };
+            });
+        });
+        if !self.break_log.is_empty() {
+            ui.collapsing(format!("Broken links ({})", self.break_log.len()), |ui| {
+                egui::ScrollArea::from_max_height(120.0).show(ui, |ui| {
+                    for line in self.break_log.iter().rev() {
+                        ui.label(line);
+                    }
+                });
+                if ui.button("Clear").clicked() { self.break_log.clear() };
+            });
         }
+        ui.collapsing("Terrain", |ui| {
+            let scale = if self.display_options.zoom2x {2.0} else {1.0};
+            let middle = (self.width as f64 / scale / 2.0).round();
+            ui.horizontal(|ui| {
+                if ui.button("Line").clicked() { self.add_obstacle(Obstacle::line(middle - 100.0, 40.0, middle + 100.0, 40.0)) };
+                if ui.button("Ramp").clicked() { self.add_obstacle(Obstacle::ramp(middle - 100.0, 200.0, 60.0)) };
+                if ui.button("Steps").clicked() { self.add_obstacle(Obstacle::steps(middle - 100.0, 5, 40.0, 15.0)) };
+                if ui.button("Box").clicked() { self.add_obstacle(Obstacle::rectangle(middle - 30.0, 0.0, 60.0, 60.0)) };
+            });
+            if let Some(o) = self.sel_obstacle {
+                ui.horizontal(|ui| {
+                    if ui.button("🗑️"[0..4].to_string()).on_hover_ui(|ui| {ui.label("Delete");}).clicked() {
+                        self.delete();
+                    }
+                    if ui.checkbox(&mut self.world.terrain[o].closed, "Closed").changed() { self.edited("Change terrain") };
+                    if ui.button("Add point").clicked() {
+                        let &(x, y) = self.world.terrain[o].points.last().unwrap_or(&(middle, 0.0));
+                        self.world.terrain[o].points.push((x + 20.0, y));
+                        self.edited("Change terrain");
+                    }
+                });
+                // the delete button above may have just taken it away
+                if self.sel_obstacle.is_some() {
+                    let mut remove = None;
+                    egui::Grid::new("terraintable").show(ui, |ui| {
+                        for p in 0..self.world.terrain[o].points.len() {
+                            let point = &mut self.world.terrain[o].points[p];
+                            let changed = ui.add(egui::DragValue::new(&mut point.0).speed(0.5).prefix("x: ")).changed()
+                                | ui.add(egui::DragValue::new(&mut point.1).speed(0.5).prefix("y: ")).changed();
+                            if changed { self.edited("Move terrain") };
+                            if self.world.terrain[o].points.len() > 2 && ui.small_button("✖").on_hover_text("Remove point").clicked() { remove = Some(p) };
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(p) = remove {
+                        self.world.terrain[o].points.remove(p);
+                        self.hover_point = None;
+                        self.edited("Change terrain");
+                    }
+                }
+            } else {
+                ui.label("Drag the corners of an obstacle to reshape it.");
+            }
+        });
+        ui.collapsing("Load Preset", |ui| {
+            let mut chosen = None;
+            if ui.button("Blank").clicked() { chosen = Some(Presets::Blank)};
+            if ui.button("Walker").clicked() { chosen = Some(Presets::Walker)};
+            if ui.button("AntiGrav").clicked() { chosen = Some(Presets::AntiGrav)};
+            if ui.button("Dancer").clicked() { chosen = Some(Presets::Dancer)};
+            if ui.button("Unicycle").clicked() { chosen = Some(Presets::Unicycle)};
+            if ui.button("Jumper").clicked() { chosen = Some(Presets::Jumper)};
+            if ui.button("SpikeBall").clicked() { chosen = Some(Presets::Spikeball)};
+            if ui.button("Muscles").clicked() { chosen = Some(Presets::Muscles)};    
+            if let Some(preset) = chosen { self.replace_scene(Replace::Preset(preset)) }
+        });
         if self.sel_vertex == None && self.sel_link == None {
             egui::CollapsingHeader::new("No selection").default_open(true).show(ui,|ui| {
                 if ui.add(egui::Button::new("🔄")).on_hover_ui(|ui| {ui.label("Reset lengths of all links");}).clicked() {
@@ -896,7 +1136,7 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
                     egui::Grid::new("postable").show(ui, |ui|{
                         ui.add(egui::Label::new("True Length"));
                         ui.horizontal(|ui| {
-                            ui.add(egui::DragValue::new(&mut self.links[n].length).speed(0.5));
+                            if ui.add(egui::DragValue::new(&mut self.world.links[n].length).speed(0.5)).changed() { self.edited("Change link") };
                             if ui.add(egui::Button::new("🔄")).on_hover_ui(|ui| {ui.label("Reset true length to actual length");}).clicked() {
                                 self.reset_link(n);
                             };
@@ -904,7 +1144,40 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
                         ui.end_row();
                     
                         ui.add(egui::Label::new("Tension"));
-                        ui.add(egui::DragValue::new(&mut self.links[n].tension).speed(0.01).clamp_range(0.0..=1.5))
+                        if ui.add(egui::DragValue::new(&mut self.world.links[n].tension).speed(0.01).clamp_range(0.0..=1.5)).changed() { self.edited("Change link") };
+                        ui.end_row();
+
+                        ui.add(egui::Label::new("Damping"));
+                        if ui.add(egui::DragValue::new(&mut self.world.links[n].damping).speed(0.01).clamp_range(0.0..=1.0)).changed() { self.edited("Change link") };
+                        ui.end_row();
+
+                        ui.add(egui::Label::new("Kind"));
+                        ui.horizontal(|ui| {
+                            for &(kind, name, hint) in &[(LinkKind::Spring, "Spring", "Pulls and pushes back to its length"), (LinkKind::Rope, "Rope", "Only pulls, going slack when squashed"), (LinkKind::Strut, "Strut", "Only pushes, going slack when stretched"), (LinkKind::Rigid, "Rigid", "Always exactly its length")] {
+                                if ui.selectable_label(self.world.links[n].kind == kind, name).on_hover_text(hint).clicked() && self.world.links[n].kind != kind {
+                                    self.world.links[n].kind = kind;
+                                    self.edited("Change link");
+                                }
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.add(egui::Label::new("Muscle group"));
+                        self.group_picker(ui, n);
+                        ui.end_row();
+
+                        ui.add(egui::Label::new("Breaks at"));
+                        ui.horizontal(|ui| {
+                            let mut percent = self.world.links[n].break_strain * 100.0;
+                            if ui.add(egui::DragValue::new(&mut percent).speed(1.0).clamp_range(0.0..=1000.0).suffix("% strain")).on_hover_text("0 for never; only when Breakable links is on").changed() {
+                                self.world.links[n].break_strain = percent / 100.0;
+                                self.edited("Change link");
+                            };
+                            if ui.add(egui::Button::new("🔄")).on_hover_ui(|ui| {ui.label("Reset to the default");}).clicked() {
+                                self.world.links[n].break_strain = DEFAULT_BREAK_STRAIN;
+                                self.edited("Change link");
+                            };
+                        });
                     });
                 });
             } else if let Some(n) = self.sel_vertex {
@@ -931,7 +1204,7 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
                         });
                         egui::Grid::new("postable2").show(ui, |ui|{
                         
-                            if self.vertices[n].radius == 0 {
+                            if self.world.vertices[n].radius == 0 {
                                 ui.label("Wheel");
                                 if ui.button("Add ").clicked() {
                                     self.set_wheel(n, 20);
@@ -939,17 +1212,55 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
                                 ui.end_row();
                             } else {
                                 ui.add(egui::Label::new("Wheel"));
-                                if ui.add(egui::DragValue::new(&mut self.vertices[n].radius).speed(0.5)).changed() {
-                                    self.vertices[n].wheel = self.vertices[n].radius > 0;
+                                if ui.add(egui::DragValue::new(&mut self.world.vertices[n].radius).speed(0.5)).changed() {
+                                    self.world.vertices[n].wheel = self.world.vertices[n].radius > 0;
+                                    self.edited("Change wheel");
                                 };
                                 ui.end_row();
-                            }                    
+
+                                ui.add(egui::Label::new("Motor"));
+                                ui.horizontal(|ui| {
+                                    for &(kind, name, hint) in &[(MotorKind::Off, "Off", "Spins freely"), (MotorKind::Torque, "Torque", "Pushes the wheel round steadily"), (MotorKind::Speed, "Speed", "Drives the wheel at a set speed")] {
+                                        if ui.selectable_label(self.world.vertices[n].motor.kind == kind, name).on_hover_text(hint).clicked() && self.world.vertices[n].motor.kind != kind {
+                                            self.world.vertices[n].motor.kind = kind;
+                                            self.edited("Change motor");
+                                        }
+                                    }
+                                });
+                                ui.end_row();
+                                if self.world.vertices[n].motor.kind != MotorKind::Off {
+                                    let motor = &mut self.world.vertices[n].motor;
+                                    let mut changed = false;
+                                    ui.add(egui::Label::new(if motor.kind == MotorKind::Speed { "Max torque" } else { "Torque" }));
+                                    changed |= ui.add(egui::DragValue::new(&mut motor.torque).speed(0.01).clamp_range(-5.0..=5.0)).changed();
+                                    ui.end_row();
+                                    if motor.kind == MotorKind::Speed {
+                                        ui.add(egui::Label::new("Speed"));
+                                        changed |= ui.add(egui::DragValue::new(&mut motor.speed).speed(0.1).clamp_range(-50.0..=50.0)).changed();
+                                        ui.end_row();
+                                    }
+                                    ui.add(egui::Label::new("Follow cycle"));
+                                    changed |= ui.checkbox(&mut motor.cycle, "").on_hover_text("Drive forwards for half the muscle cycle and backwards for the rest").changed();
+                                    ui.end_row();
+                                    if changed { self.edited("Change motor") }
+                                }
+                            }
+                            ui.add(egui::Label::new("Mass"));
+                            if ui.add(egui::DragValue::new(&mut self.world.vertices[n].mass).speed(0.05).clamp_range(0.1..=100.0)).changed() { self.edited("Change mass") };
+                            ui.end_row();
+
+                            ui.add(egui::Label::new("Pinned"));
+                            if ui.checkbox(&mut self.world.vertices[n].pinned, "").on_hover_ui(|ui| {ui.label("Hold this vertex fixed in place");}).changed() {
+                                self.edited(if self.world.vertices[n].pinned { "Pin vertex" } else { "Unpin vertex" });
+                            };
+                            ui.end_row();
+
                             ui.add(egui::Label::new("X"));
-                            ui.add(egui::DragValue::new(&mut self.vertices[n].x).speed(0.5));
+                            if ui.add(egui::DragValue::new(&mut self.world.vertices[n].x).speed(0.5)).changed() { self.edited("Move vertex") };
                             ui.end_row();
                         
                             ui.add(egui::Label::new("Y"));
-                            ui.add(egui::DragValue::new(&mut self.vertices[n].y).speed(0.5))
+                            if ui.add(egui::DragValue::new(&mut self.world.vertices[n].y).speed(0.5)).changed() { self.edited("Move vertex") };
                         });
                     });
                     
@@ -957,21 +1268,45 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
             }
         }
     });
+    if let Some(e) = self.load_error.clone() {
+        egui::Window::new("Error").collapsible(false).resizable(false)
+        .show(ctx, |ui| {
+            ui.colored_label(Color32::RED, e);
+            if ui.button("Dismiss").clicked() {
+                self.load_error = None;
+            }
+        });
+    }
+    if self.confirm_replace.is_some() {
+        egui::Window::new("Discard unsaved changes?").collapsible(false).resizable(false)
+        .show(ctx, |ui| {
+            ui.label("The current scene has changes that haven't been saved.");
+            ui.horizontal(|ui| {
+                if ui.button("Discard").clicked() {
+                    if let Some(replace) = self.confirm_replace.take() { self.do_replace(replace) }
+                }
+                if ui.button("Cancel").clicked() { self.confirm_replace = None }
+            });
+        });
+    }
+    if self.show_diagnostics { self.diagnostics_window(ctx) }
     egui::Window::new("Environment").fixed_size(egui::Vec2::new(40.0,100.0))
     .show(ctx, |ui| {
             ui.vertical_centered_justified(|ui| {
                 ui.horizontal(|ui| {
-                    ui.add(vertslide::VSlider::new(&mut self.environment.gravity, -1.0..=1.0).text("Gravity"));
+                    if ui.add(vertslide::VSlider::new(&mut self.world.environment.gravity, -1.0..=1.0).text("Gravity")).changed() { self.unsaved_changes = true };
                     
                     ui.vertical(|ui| {
                         ui.add(egui::Label::new("Atmosphere").wrap(false));
-                        ui.add(egui::Slider::new(&mut self.environment.atmosphere, 0.0..=1.0));
+                        if ui.add(egui::Slider::new(&mut self.world.environment.atmosphere, 0.0..=1.0)).changed() { self.unsaved_changes = true };
                         ui.label("Wind");
-                        ui.add(egui::Slider::new(&mut self.environment.left_wind, -20.0..=20.0));
+                        if ui.add(egui::Slider::new(&mut self.world.environment.left_wind, -20.0..=20.0)).changed() { self.unsaved_changes = true };
                         ui.label("Wall Friction");
-                        ui.add(egui::Slider::new(&mut self.environment.wall_friction, -1.0..=2.0));
+                        if ui.add(egui::Slider::new(&mut self.world.environment.wall_friction, -1.0..=2.0)).changed() { self.unsaved_changes = true };
                         ui.label("Wall Bounce");
-                        ui.add(egui::Slider::new(&mut self.environment.wall_bounce, 0.0..=2.0));
+                        if ui.add(egui::Slider::new(&mut self.world.environment.wall_bounce, 0.0..=2.0)).changed() { self.unsaved_changes = true };
+                        ui.label("Link Damping").on_hover_text("Given to new links, to stop springy creatures jittering");
+                        if ui.add(egui::Slider::new(&mut self.world.environment.damping, 0.0..=1.0)).changed() { self.unsaved_changes = true };
                     })
                 
                     /*
@@ -979,25 +1314,56 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
                     */
                 });
                 
-                if ui.selectable_label(self.walls.ceiling,"Ceiling").clicked() {
-                    self.walls.ceiling = !self.walls.ceiling;
+                if ui.selectable_label(self.world.walls.ceiling,"Ceiling").clicked() {
+                    self.world.walls.ceiling = !self.world.walls.ceiling;
+                    self.edited("Toggle ceiling");
                 }
                 ui.columns(2, |columns| {
                     columns[0].centered_and_justified(|ui| 
-                        if ui.selectable_label(self.walls.left,"Wall L").clicked() {
-                            self.walls.left = !self.walls.left;
+                        if ui.selectable_label(self.world.walls.left,"Wall L").clicked() {
+                            self.world.walls.left = !self.world.walls.left;
+                            self.edited("Toggle left wall");
                         });
-                    columns[1].centered_and_justified(|ui| if ui.selectable_label(self.walls.right,"Wall R").clicked() {
-                        self.walls.right = !self.walls.right;
+                    columns[1].centered_and_justified(|ui| if ui.selectable_label(self.world.walls.right,"Wall R").clicked() {
+                        self.world.walls.right = !self.world.walls.right;
+                        self.edited("Toggle right wall");
                     });
                 });
-                if ui.selectable_label(self.walls.floor,"Floor").clicked() {
-                    self.walls.floor = !self.walls.floor;
+                if ui.selectable_label(self.world.walls.floor,"Floor").clicked() {
+                    self.world.walls.floor = !self.world.walls.floor;
+                    self.edited("Toggle floor");
+                }
+                ui.label("Collisions");
+                ui.horizontal(|ui| {
+                    for &(collisions, name, hint) in &[(Collisions::Off, "Off", "Bodies pass through each other"), (Collisions::BetweenBodies, "Bodies", "Separate creatures collide"), (Collisions::All, "All", "Parts of the same creature collide too")] {
+                        if ui.selectable_label(self.world.environment.collisions == collisions, name).on_hover_text(hint).clicked() {
+                            self.world.environment.collisions = collisions;
+                            self.unsaved_changes = true;
+                        }
+                    }
+                });
+                ui.label("Integrator");
+                ui.horizontal(|ui| {
+                    for &(integrator, name, hint) in &[(Integrator::Legacy, "Legacy", "The original physics, as old creatures were built for"), (Integrator::Verlet, "Verlet", "Position-based, and the same whatever order links were made in"), (Integrator::Pbd, "PBD", "Position-based with several passes, for stiff creatures")] {
+                        if ui.selectable_label(self.world.environment.integrator == integrator, name).on_hover_text(hint).clicked() {
+                            self.world.environment.integrator = integrator;
+                            self.unsaved_changes = true;
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.set_enabled(self.world.environment.integrator != Integrator::Legacy);
+                    ui.label("Substeps");
+                    if ui.add(egui::DragValue::new(&mut self.world.environment.substeps).speed(0.1).clamp_range(1..=crate::world::MAX_SUBSTEPS)).on_hover_text("Split each tick up, for stiffer links and softer landings").changed() { self.unsaved_changes = true };
+                });
+                if ui.selectable_label(self.world.environment.breakable_links, "Breakable links").on_hover_text("Links snap when strained past their limit").clicked() {
+                    self.world.environment.breakable_links = !self.world.environment.breakable_links;
+                    self.unsaved_changes = true;
                 }
             });
         
     });
-    egui::Window::new("Muscles").fixed_size(egui::Vec2::new(250.0,100.0))
+    egui::Window::new("Muscles").fixed_size(egui::Vec2::new(320.0,100.0))
     .show(ctx, |ui| {
         ui.vertical(|ui| {
             let rect = ui.max_rect_finite();
@@ -1005,144 +1371,188 @@ fn update(&mut self, ctx: &egui::CtxRef, _: &mut epi::Frame<'_>) {
             
             ui.painter().rect_filled(rect, 4.0, Color32::WHITE);
             
-            ui.painter().line_segment([rect.left_top() + egui::Vec2::new(self.cycle_time as f32 * rect.width() / 200.0,0.0) , rect.left_bottom() + egui::Vec2::new(self.cycle_time as f32 * rect.width() / 200.0,0.0)], egui::Stroke::new(1.0,Color32::GOLD) );
-            ui.painter().line_segment([rect.left_top() + egui::Vec2::new(50.0 * rect.width() / 200.0,0.0) , rect.left_bottom() + egui::Vec2::new(50.0 * rect.width() / 200.0,0.0)], egui::Stroke::new(1.0,Color32::LIGHT_GRAY) );
-            ui.painter().line_segment([rect.left_top() + egui::Vec2::new(100.0 * rect.width() / 200.0,0.0) , rect.left_bottom() + egui::Vec2::new(100.0 * rect.width() / 200.0,0.0)], egui::Stroke::new(1.0,Color32::LIGHT_GRAY) );
-            ui.painter().line_segment([rect.left_top() + egui::Vec2::new(150.0 * rect.width() / 200.0,0.0) , rect.left_bottom() + egui::Vec2::new(150.0 * rect.width() / 200.0,0.0)], egui::Stroke::new(1.0,Color32::LIGHT_GRAY) );
-            ui.painter().line_segment([rect.left_top() + egui::Vec2::new(0.0,rect.height()/2.0) , rect.right_top() + egui::Vec2::new(0.0,rect.height()/2.0)], egui::Stroke::new(1.0,Color32::BLACK) );
-            for i in 0..self.links.len() {
-                let cycle_size = 200;
-                
-                if self.links[i].push_span > 0 && self.links[i].push_strength != 0.0 && self.links[i].push_timing <= cycle_size {
-
-                    let col = if Some(i) == self.sel_link { Color32::RED } else if Some(i) == self.hover_link { Color32::BLUE } else { Color32::GRAY };
-                    let p1 = egui::Vec2::new(self.links[i].push_timing as f32 * rect.width() / cycle_size as f32,(rect.height()/2.0)  - (self.links[i].push_strength as f32 * (rect.height()/2.0)/ 20.0));
-                    let p2 = egui::Vec2::new((self.links[i].push_timing as f32 - self.links[i].push_span as f32) * rect.width() / cycle_size as f32,rect.height()/2.0);
-                    let p3 = egui::Vec2::new((self.links[i].push_timing as f32 + self.links[i].push_span as f32) * rect.width() / cycle_size as f32,rect.height()/2.0);
-                    let p = ui.painter_at(rect);
-                
-                    p.line_segment([rect.left_top() + p1, rect.left_top() + p2], egui::Stroke::new(1.0,col) );
-                    p.line_segment([rect.left_top() + p1, rect.left_top() + p3], egui::Stroke::new(1.0,col) );
-                    p.line_segment([rect.left_top() + p1 + egui::Vec2::new(rect.width(),0.0), rect.left_top() + p2 + egui::Vec2::new(rect.width(),0.0)], egui::Stroke::new(1.0,col) );
-                    p.line_segment([rect.left_top() + p1 + egui::Vec2::new(rect.width(),0.0), rect.left_top() + p3 + egui::Vec2::new(rect.width(),0.0)], egui::Stroke::new(1.0,col) );
-                    p.line_segment([rect.left_top() + p1 - egui::Vec2::new(rect.width(),0.0), rect.left_top() + p2 - egui::Vec2::new(rect.width(),0.0)], egui::Stroke::new(1.0,col) );
-                    p.line_segment([rect.left_top() + p1 - egui::Vec2::new(rect.width(),0.0), rect.left_top() + p3 - egui::Vec2::new(rect.width(),0.0)], egui::Stroke::new(1.0,col) );
+            // the clock as the selected link's muscle group sees it
+            let clock = self.sel_link.and_then(|i| self.world.group_time(self.world.links[i].phase)).unwrap_or(self.world.cycle_time);
+            let cycle = self.world.environment.cycle_length as f64;
+            timeline::draw_grid(ui, rect, cycle, clock);
+            // only the selected muscle, and whichever link is under the pointer; the tracks
+            // below show the rest
+            for i in self.sel_link.into_iter().chain(self.hover_link.filter(|&i| Some(i) != self.sel_link)) {
+                let link = &self.world.links[i];
+                let col = if Some(i) == self.sel_link { Color32::RED } else { Color32::BLUE };
+                let points = match timeline::outline(link, cycle) {
+                    Some(points) => points.iter().map(|&(t, e)| timeline::timeline_pos(rect, cycle, t, e)).collect::<Vec<_>>(),
+                    None => continue,
+                };
+                if Some(i) == self.sel_link {
+                    for (k, point) in points[..link.keyframes.len()].iter().enumerate() {
+                        let radius = if self.drag_key == Some(k) { 4.0 } else { 3.0 };
+                        ui.painter_at(rect).circle_filled(*point, radius, col);
+                    }
                 }
+                timeline::draw_outline(ui, rect, &points, col);
             }
+            let keyed = self.sel_link.filter(|&i| !self.world.links[i].keyframes.is_empty());
+            if let Some(i) = keyed { self.edit_keyframes(ui, &response, rect, i) }
             if let Some(pos) = response.hover_pos() {
                 if self.sel_link.is_some() {
                     ui.painter().line_segment([egui::Pos2::new(rect.left(),pos.y) , egui::Pos2::new(rect.right(),pos.y)], egui::Stroke::new(1.0,Color32::LIGHT_GRAY) );
                     ui.painter().line_segment([egui::Pos2::new(pos.x,rect.top()) , egui::Pos2::new(pos.x,rect.bottom())], egui::Stroke::new(1.0,Color32::LIGHT_GRAY) );
                 }
-                if rect.contains(pos) {
+                if rect.contains(pos) && keyed.is_none() {
                     if let Some(spos) = ui.input().pointer.press_origin() {
                         let adj_pos = egui::Pos2::new(spos.x - rect.left(), pos.y - rect.top()) ;
 
-                        let x = 200.0 * adj_pos.x / rect.width();
+                        let x = cycle as f32 * adj_pos.x / rect.width();
                         let y = (rect.height() - adj_pos.y - (rect.height()/2.0)) * (20.0)/ (rect.height()/2.0);
                         if let Some(i) = self.sel_link {
-                           self.links[i].push_strength = y as f64;
-                           self.links[i].push_timing = x as i32;
-                           self.links[i].push_span = ((spos.x - pos.x).abs() * 200.0 / rect.width()) as i32;
-                           if self.links[i].push_span == 0 && self.links[i].push_strength > 5.0 {
-                              self.links[i].push_span = 5;
+                           self.world.links[i].push_strength = y as f64;
+                           self.world.links[i].push_timing = x as i32;
+                           self.world.links[i].push_span = ((spos.x - pos.x).abs() * cycle as f32 / rect.width()) as i32;
+                           if self.world.links[i].push_span == 0 && self.world.links[i].push_strength > 5.0 {
+                              self.world.links[i].push_span = 5;
                            }
-                           if self.links[i].push_span > 100 {
-                               self.links[i].push_span = 100;
+                           let longest = (self.world.environment.cycle_length / 2).max(1);
+                           if self.world.links[i].push_span > longest {
+                               self.world.links[i].push_span = longest;
                            }
+                           self.edited("Change muscle");
                         }
 
                     }
                     
                 }
             }
+            self.muscle_tracks(ui);
+            egui::CollapsingHeader::new("Muscle groups").default_open(false).show(ui, |ui| self.muscle_groups(ui));
             ui.horizontal(|ui| {
                 ui.label("Speed");
-                ui.add(egui::Slider::new(&mut self.environment.clock_speed, -10..=10));
+                if ui.add(egui::Slider::new(&mut self.world.environment.clock_speed, -10.0..=10.0)).changed() { self.unsaved_changes = true };
                 if ui.button("⏪"[0..3].to_string()).clicked() {
-                    self.environment.clock_speed = -self.environment.clock_speed;
+                    self.world.environment.clock_speed = -self.world.environment.clock_speed;
+                    self.unsaved_changes = true;
                 };
-                if ui.button(if self.clock_pause { "▶️"[0..3].to_string() } else { "⏸️"[0..3].to_string() } ).clicked() {
-                    self.clock_pause = !self.clock_pause;
+                if ui.button(if self.world.clock_pause { "▶️"[0..3].to_string() } else { "⏸️"[0..3].to_string() } ).clicked() {
+                    self.world.clock_pause = !self.world.clock_pause;
                 };
             });
             ui.horizontal(|ui| {
-                ui.checkbox(&mut self.auto_reverse_enabled,"Auto Reverse");
+                if ui.checkbox(&mut self.world.auto_reverse_enabled,"Auto Reverse").changed() { self.edited("Toggle auto reverse") };
+                let mut length = self.world.environment.cycle_length;
+                ui.label("Cycle");
+                if ui.add(egui::DragValue::new(&mut length).speed(1).clamp_range(MIN_CYCLE_LENGTH..=MAX_CYCLE_LENGTH).suffix(" ticks"))
+                    .on_hover_text("How many ticks the muscles take to go round once. Their timings stretch to fit").changed() {
+                    self.world.set_cycle_length(length);
+                    self.edited("Change cycle length");
+                }
             });
             if let Some(n) = self.sel_link {
                 egui::CollapsingHeader::new(format!("Fine tuning (Link {})",n)).default_open(false).show(ui, |ui| {
+                    if !self.world.links[n].keyframes.is_empty() {
+                        self.keyframe_list(ui, n);
+                        return
+                    }
                     egui::Grid::new("postable3").show(ui, |ui|{
                         ui.label("Time");
-                        ui.add(egui::DragValue::new(&mut self.links[n].push_timing).speed(1).clamp_range(0..=200));
+                        if ui.add(egui::DragValue::new(&mut self.world.links[n].push_timing).speed(1).clamp_range(0..=self.world.environment.cycle_length)).changed() { self.edited("Change muscle") };
                         ui.end_row();
                         ui.label("Span");
-                        ui.add(egui::DragValue::new(&mut self.links[n].push_span).speed(1).clamp_range(0..=100));
+                        if ui.add(egui::DragValue::new(&mut self.world.links[n].push_span).speed(1).clamp_range(0..=self.world.environment.cycle_length / 2)).changed() { self.edited("Change muscle") };
                         ui.end_row();
                         ui.label("Force");
-                        ui.add(egui::DragValue::new(&mut self.links[n].push_strength).speed(0.1));
+                        if ui.add(egui::DragValue::new(&mut self.world.links[n].push_strength).speed(0.1)).changed() { self.edited("Change muscle") };
+                        ui.end_row();
+                        ui.label("Shape");
+                        ui.horizontal_wrapped(|ui| {
+                            for &(waveform, name, hint) in &[(Waveform::Triangle, "Triangle", "Ramps straight up and down"), (Waveform::Sine, "Sine", "Eases in and out along a curve"), (Waveform::Square, "Square", "Holds full strength for the whole span"), (Waveform::Smoothstep, "Smooth", "A triangle with its corners rounded off"), (Waveform::Sawtooth, "Sawtooth", "Builds up across the span, then lets go")] {
+                                if ui.selectable_label(self.world.links[n].waveform == waveform, name).on_hover_text(hint).clicked() && self.world.links[n].waveform != waveform {
+                                    self.world.links[n].waveform = waveform;
+                                    self.edited("Change muscle");
+                                }
+                            }
+                        });
                         ui.end_row();
                         if ui.button("Remove").clicked() {
-                            self.links[n].push_strength = 0.0;
-                            self.links[n].push_timing = 0;
-                            self.links[n].push_span = 0;
+                            self.world.links[n].push_strength = 0.0;
+                            self.world.links[n].push_timing = 0;
+                            self.world.links[n].push_span = 0;
+                            self.edited("Remove muscle");
                         };
                     });
+                    if ui.button("Use keyframes").on_hover_text("Edit it as points on the timeline instead, so it can push more than once a cycle").clicked() {
+                        self.use_keyframes(n);
+                    }
                 });
             }
         });
         
     });
+    // Everything done since the mouse went down is one undo step. While simulating the
+    // bodies move on their own, so that whole run is folded into one step on returning.
+    if self.mode != 1 && !ctx.input().pointer.any_down() {
+        self.history.checkpoint(&self.world);
+    }
     ctx.request_repaint()
  }
 fn name(&self) -> &str { "botz" }
+fn setup(&mut self, _ctx: &egui::CtxRef, _frame: &mut epi::Frame<'_>, storage: Option<&dyn epi::Storage>) {
+    if let Some(recent) = storage.and_then(|s| s.get_string("recent_files")) {
+        self.recent_files = recent.lines().map(std::path::PathBuf::from).collect();
+    }
+}
+fn save(&mut self, storage: &mut dyn epi::Storage) {
+    let recent: Vec<String> = self.recent_files.iter().map(|p| p.display().to_string()).collect();
+    storage.set_string("recent_files", recent.join("\n"));
+}
 
 }
 pub fn make_start() -> State {
 
     let mut s = State {
-        auto_reverse_cycle: 0,
-        auto_reverse_enabled: true,
-        ceiling: 594.0,
+        world: World::default(),
         current_phase: 0,
-        cycle_time: 0,
+        ticks_per_second: 60.0,
+        max_catch_up: 8,
+        tick_accumulator: 0.0,
+        last_frame_time: None,
+        file_path: None,
+        recent_files: Vec::new(),
+        load_error: None,
+        unsaved_changes: false,
+        confirm_replace: None,
+        history: history::History::default(),
+        recorder: None,
+        player: None,
+        replay: None,
+        ticks_run: 0,
+        break_log: Vec::new(),
+        snaps: Vec::new(),
+        show_diagnostics: false,
+        diagnostics: diagnostics::DiagnosticsLog::default(),
         drag_dot: None,
-        environment: Environment {
-            atmosphere: 0.01,
-            clock_speed: 3,
-            gravity: 0.4,
-            left_wind: 0.0,
-            tension: 0.9,
-            wall_bounce: 0.4,
-            wall_friction: 0.7,
-        },
-        walls: Walls {
-            left: true,
-            right: true,
-            ceiling: true,
-            floor: true,
-        },
+        sel_obstacle: None,
+        hover_point: None,
+        drag_point: None,
+        drag_key: None,
+        sel_muscles: Vec::new(),
+        track_drag: None,
         height: 600,
         hover_link: None,
         hover_vertex: None,
-        links: Vec::new(),
         mode: 0,
         mouse_x: 0.0,
         mouse_y: 0.0,
-        rightwall: 797.0,
         sel_link: None,
         sel_vertex: None,
         sub_mode : 0,
         sub_mode_data: 0,
-        vertices: Vec::new(),
         width: 800,
-        clock_pause: false,
-        triangles_updated: false,
-        triangles: std::collections::HashSet::new(),
         display_options: DisplayOptions { 
             show_link_handles: false, 
             zoom2x: false, 
             show_links: true, 
             show_vertices: true, 
+            show_mass: false,
             show_wheels: true,
             num_wheel_spokes: 6,
             shade_body: true,
@@ -1156,6 +1566,7 @@ pub fn make_start() -> State {
             link_compressed_color: egui::Color32::from_rgb(0, 0, 255),
             selection_color: egui::Color32::from_rgb(255, 0, 255),
             hover_color: egui::Color32::from_rgb(255, 0, 0),
+            terrain_color: egui::Color32::from_rgb(110, 90, 70),
             vertex_color: egui::Color32::from_rgb(0, 0, 0),
             link_handle_color: egui::Color32::from_rgb(0, 0, 0),
             wheel_color: egui::Color32::from_rgb(0, 0, 0),
@@ -1164,4 +1575,66 @@ pub fn make_start() -> State {
     };
     s.legacy_parse(include_str!("../walker.botz")).unwrap();
     s
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_at(frames_per_second: f64) -> State {
+        let mut s = make_start();
+        s.ticks_per_second = 64.0;
+        for frame in 0..=(2.0 * frames_per_second) as u32 {
+            s.advance_simulation(frame as f64 / frames_per_second);
+        }
+        s
+    }
+
+    #[test]
+    fn simulation_speed_ignores_frame_rate() {
+        let slow = run_at(32.0);
+        let fast = run_at(128.0);
+        assert_eq!(slow.world.vertices, fast.world.vertices);
+        assert_eq!(fast.world.cycle_time, run_at(64.0).world.cycle_time);
+    }
+
+    #[test]
+    fn presets_wait_before_discarding_unsaved_changes() {
+        let mut s = make_start();
+        s.replace_scene(Replace::Preset(Presets::Muscles));
+        assert!(s.confirm_replace.is_none());
+        let loaded = s.world.vertices.len();
+        s.world.add_vertex(10.0, 10.0, 0.0, 0.0, 0, 0.0, 0);
+        s.edited("Add vertex");
+        s.replace_scene(Replace::Preset(Presets::Muscles));
+        assert_eq!(s.world.vertices.len(), loaded + 1);
+        let replace = s.confirm_replace.take().unwrap();
+        s.do_replace(replace);
+        assert_eq!(s.world.vertices.len(), loaded);
+        assert!(!s.unsaved_changes);
+    }
+
+    #[test]
+    fn typing_in_a_text_box_is_not_a_shortcut() {
+        let mut s = make_start();
+        s.load_presets(Presets::Walker).unwrap();
+        s.sel_link = Some(0);
+        let links = s.world.links.len();
+        let mut ctx = egui::CtxRef::default();
+        let backspace = || {
+            let mut input = egui::RawInput::default();
+            input.events.push(egui::Event::Key { key: egui::Key::Backspace, pressed: true, modifiers: Default::default() });
+            input
+        };
+        ctx.begin_frame(backspace());
+        ctx.memory().request_focus(egui::Id::new("group name"));
+        s.keyboard_shortcuts(&ctx);
+        assert_eq!(s.world.links.len(), links);
+        ctx.memory().surrender_focus(egui::Id::new("group name"));
+        let _ = ctx.end_frame();
+        ctx.begin_frame(backspace());
+        s.keyboard_shortcuts(&ctx);
+        assert_eq!(s.world.links.len(), links - 1);
+        let _ = ctx.end_frame();
+    }
+}
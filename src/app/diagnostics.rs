@@ -0,0 +1,81 @@
+//! The diagnostics window, which plots `World::diagnostics` over the last few hundred
+//! ticks and points out any vertex that has gone NaN or infinite.
+use super::State;
+use crate::world::{Diagnostics, World};
+use egui::widgets::plot::{Line, Plot, Value, Values};
+use std::collections::VecDeque;
+
+/// How many ticks of history the plots show.
+const MAX_SAMPLES: usize = 600;
+
+#[derive(Default)]
+pub struct DiagnosticsLog {
+    samples: VecDeque<(u64, Diagnostics)>,
+    /// Where each vertex last was while it was still finite, to show where a bad one went wrong.
+    last_finite: Vec<(f64, f64)>,
+    /// The tick on which something first went non-finite.
+    first_bad: Option<u64>,
+}
+impl DiagnosticsLog {
+    pub fn clear(&mut self) {
+        *self = DiagnosticsLog::default();
+    }
+    pub fn record(&mut self, tick: u64, world: &World) {
+        let d = world.diagnostics();
+        self.last_finite.resize(world.vertices.len(), (0.0, 0.0));
+        for (i, v) in world.vertices.iter().enumerate() {
+            if v.x.is_finite() && v.y.is_finite() { self.last_finite[i] = (v.x, v.y) }
+        }
+        if !d.non_finite.is_empty() && self.first_bad.is_none() { self.first_bad = Some(tick) }
+        self.samples.push_back((tick, d));
+        if self.samples.len() > MAX_SAMPLES { self.samples.pop_front(); }
+    }
+    /// The vertices that were non-finite as of the last tick recorded.
+    pub fn bad(&self) -> &[usize] {
+        self.samples.back().map_or(&[], |(_, d)| &d.non_finite)
+    }
+    /// Where vertex `i` last was before it went bad.
+    pub fn last_finite(&self, i: usize) -> Option<(f64, f64)> {
+        self.last_finite.get(i).copied()
+    }
+    fn plot(&self, ui: &mut egui::Ui, name: &str, value: impl Fn(&Diagnostics) -> f64) {
+        let latest = self.samples.back().map_or(0.0, |(_, d)| value(d));
+        ui.label(format!("{}: {:.3}", name, latest));
+        let points = self.samples.iter().map(|(tick, d)| Value::new(*tick as f64, value(d))).filter(|p| p.y.is_finite());
+        ui.add(Plot::new(name).line(Line::new(Values::from_values_iter(points))).height(60.0).allow_zoom(false).allow_drag(false).include_y(0.0));
+    }
+}
+
+impl State {
+    pub(super) fn diagnostics_window(&mut self, ctx: &egui::CtxRef) {
+        let mut open = self.show_diagnostics;
+        egui::Window::new("Diagnostics").open(&mut open).default_width(280.0).show(ctx, |ui| {
+            if let Some(tick) = self.diagnostics.first_bad {
+                ui.colored_label(egui::Color32::RED, format!("⚠ Went non-finite at tick {}", tick));
+                let bad = self.diagnostics.bad().to_vec();
+                ui.horizontal_wrapped(|ui| {
+                    for i in bad {
+                        if ui.button(format!("Vertex {}", i)).on_hover_text("Select it").clicked() {
+                            self.clear_multi_select();
+                            self.sel_link = None;
+                            self.sel_vertex = Some(i);
+                            self.world.vertices[i].selected = true;
+                        }
+                    }
+                });
+            } else if self.diagnostics.samples.is_empty() {
+                ui.label("Run the simulation to see how it's going.");
+            }
+            let log = &self.diagnostics;
+            log.plot(ui, "Kinetic energy", |d| d.kinetic);
+            log.plot(ui, "Potential energy", |d| d.potential);
+            log.plot(ui, "Spring energy", |d| d.spring);
+            log.plot(ui, "Centre of mass speed", |d| (d.velocity.0 * d.velocity.0 + d.velocity.1 * d.velocity.1).sqrt());
+            log.plot(ui, "Max link strain", |d| d.max_strain.map_or(0.0, |(_, s)| s));
+            if let Some((link, _)) = log.samples.back().and_then(|(_, d)| d.max_strain) {
+                ui.label(format!("Most strained: link {}", link));
+            }
+        });
+        self.show_diagnostics = open;
+    }
+}
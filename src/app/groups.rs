@@ -0,0 +1,121 @@
+//! Muscle groups. A link's phase says which group its muscle is in, and each group can run
+//! its muscles ahead of the main cycle or faster than it, or be switched off, even while the
+//! simulation is running.
+use super::State;
+use crate::world::MuscleGroup;
+
+impl State {
+    /// What to call group `phase` in the editor.
+    pub(super) fn group_name(&self, phase: u8) -> String {
+        match self.world.groups.get(phase as usize) {
+            Some(group) if !group.name.is_empty() => group.name.clone(),
+            _ => format!("Group {}", phase),
+        }
+    }
+    /// A choice of group for link `n`, in the link panel.
+    pub(super) fn group_picker(&mut self, ui: &mut egui::Ui, n: usize) {
+        let mut phase = self.world.links[n].phase;
+        let count = self.world.groups.len().max(phase as usize + 1);
+        egui::ComboBox::from_id_source("link_group").selected_text(self.group_name(phase)).show_ui(ui, |ui| {
+            for p in 0..count as u8 {
+                ui.selectable_value(&mut phase, p, self.group_name(p));
+            }
+        });
+        if phase != self.world.links[n].phase {
+            self.world.links[n].phase = phase;
+            self.edited("Change muscle group");
+        }
+    }
+    /// Every muscle group, under the tracks in the Muscles window. Groups that links are in
+    /// but that were never set up are shown as they behave, with the defaults, and only
+    /// join `world.groups` once they're changed.
+    pub(super) fn muscle_groups(&mut self, ui: &mut egui::Ui) {
+        // every group that a link is in, or that new links will go in, gets a row
+        let used = self.world.links.iter().map(|link| link.phase).chain(std::iter::once(self.current_phase)).max().unwrap_or(0) as usize + 1;
+        let mut groups: Vec<MuscleGroup> = (0..self.world.groups.len().max(used)).map(|phase| self.world.groups.get(phase).cloned().unwrap_or_default()).collect();
+        let chosen: Vec<usize> = if self.sel_muscles.is_empty() { self.sel_link.into_iter().collect() } else { self.sel_muscles.clone() };
+        let (mut changed, mut assign) = (false, None);
+        let cycle = self.world.environment.cycle_length;
+        egui::Grid::new("muscle_groups").show(ui, |ui| {
+            ui.label("On");
+            ui.label("Name");
+            ui.label("Offset");
+            ui.label("Speed");
+            ui.end_row();
+            for (phase, group) in groups.iter_mut().enumerate() {
+                changed |= ui.checkbox(&mut group.enabled, "").on_hover_text("Its muscles only push while it's on").changed();
+                changed |= ui.add(egui::TextEdit::singleline(&mut group.name).desired_width(70.0).hint_text(format!("Group {}", phase))).changed();
+                changed |= ui.add(egui::DragValue::new(&mut group.offset).speed(1).clamp_range(-cycle..=cycle))
+                    .on_hover_text("How many ticks its muscles run ahead of the main cycle").changed();
+                changed |= ui.add(egui::DragValue::new(&mut group.speed).speed(0.1).clamp_range(1..=8).suffix("×"))
+                    .on_hover_text("How many times its muscles go round for each turn of the main cycle. Only whole numbers, so they're back in step each time it starts over").changed();
+                if ui.selectable_label(self.current_phase as usize == phase, "New").on_hover_text("Put new links in this group").clicked() {
+                    self.current_phase = phase as u8;
+                }
+                if ui.add(egui::Button::new("Assign").enabled(!chosen.is_empty())).on_hover_text("Put the chosen muscles in this group").clicked() {
+                    assign = Some(phase as u8);
+                }
+                ui.end_row();
+            }
+        });
+        if changed {
+            self.world.groups = groups.clone();
+            self.edited("Change muscle group");
+        }
+        if let Some(phase) = assign {
+            for &i in &chosen {
+                self.world.links[i].phase = phase;
+            }
+            self.edited("Change muscle group");
+        }
+        if ui.add(egui::Button::new("Add group").enabled(groups.len() <= u8::MAX as usize)).clicked() {
+            groups.push(MuscleGroup::default());
+            self.current_phase = (groups.len() - 1) as u8;
+            self.world.groups = groups;
+            self.edited("Add muscle group");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{make_start, Presets};
+
+    /// Draws the groups grid once, as an open "Muscle groups" header would.
+    fn draw(s: &mut State) {
+        let mut ctx = egui::CtxRef::default();
+        ctx.begin_frame(egui::RawInput::default());
+        egui::CentralPanel::default().show(&ctx, |ui| s.muscle_groups(ui));
+        let _ = ctx.end_frame();
+    }
+
+    #[test]
+    fn drawing_the_groups_changes_nothing() {
+        let mut s = make_start();
+        s.load_presets(Presets::Walker).unwrap();
+        s.current_phase = 3;
+        draw(&mut s);
+        assert!(s.world.groups.is_empty());
+        assert!(!s.unsaved_changes);
+    }
+
+    #[test]
+    fn undo_gets_past_group_edits_while_they_are_drawn() {
+        let mut s = make_start();
+        s.load_presets(Presets::Walker).unwrap();
+        draw(&mut s);
+        s.history.checkpoint(&s.world);
+        assert_eq!(s.history.next_undo(&s.world), None);
+        s.world.groups = vec![MuscleGroup::default(), MuscleGroup { offset: 20, ..MuscleGroup::default() }];
+        s.edited("Change muscle group");
+        s.history.checkpoint(&s.world);
+        s.undo();
+        // a frame goes by with the grid open
+        draw(&mut s);
+        s.history.checkpoint(&s.world);
+        assert!(s.world.groups.is_empty());
+        assert_eq!(s.history.next_undo(&s.world), None);
+        assert_eq!(s.history.next_redo(&s.world), Some("Change muscle group"));
+    }
+}
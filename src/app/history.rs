@@ -0,0 +1,169 @@
+//! Undo and redo for the editor. Each entry holds the bodies and terrain as they
+//! were before and after one editor command. Rather than every command knowing how
+//! to reverse itself, the state wrapper calls `checkpoint` once the mouse is let go,
+//! and whatever changed since the last checkpoint becomes one entry; that is what
+//! folds a whole drag into a single step.
+use crate::world::{Link, MuscleGroup, Obstacle, Vertex, World};
+
+/// Oldest entries are dropped past this point.
+const MAX_ENTRIES: usize = 200;
+
+#[derive(Clone, Default)]
+struct Bodies {
+    vertices: Vec<Vertex>,
+    links: Vec<Link>,
+    terrain: Vec<Obstacle>,
+    groups: Vec<MuscleGroup>,
+    /// Changing the cycle length stretches the muscles' timings, so the two go back together.
+    cycle_length: i32,
+}
+impl Bodies {
+    /// Selection isn't part of the scene, so it's left out of snapshots and comparisons.
+    /// Everything else is compared with the derived `==`, so new fields are never missed.
+    fn of(world: &World) -> Bodies {
+        Bodies {
+            vertices: world.vertices.iter().map(|v| Vertex { selected: false, just_released: false, ..*v }).collect(),
+            links: world.links.clone(),
+            terrain: world.terrain.clone(),
+            groups: world.groups.clone(),
+            cycle_length: world.environment.cycle_length,
+        }
+    }
+    fn matches(&self, world: &World) -> bool {
+        self.vertices.len() == world.vertices.len() && self.links.len() == world.links.len()
+            && self.vertices.iter().zip(&world.vertices).all(|(a, b)| *a == Vertex { selected: false, just_released: false, ..*b })
+            && self.links == world.links
+            && self.terrain == world.terrain && self.groups == world.groups
+            && self.cycle_length == world.environment.cycle_length
+    }
+    fn restore(&self, world: &mut World) {
+        world.replace_bodies(self.vertices.clone(), self.links.clone());
+        world.terrain = self.terrain.clone();
+        world.groups = self.groups.clone();
+        world.environment.cycle_length = self.cycle_length;
+    }
+}
+
+struct Entry {
+    what: &'static str,
+    before: Bodies,
+    after: Bodies,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    committed: Bodies,
+    pending: Option<&'static str>,
+}
+impl History {
+    /// Forgets everything and takes the world as it is now as the starting point.
+    pub fn clear(&mut self, world: &World) {
+        self.undo.clear();
+        self.redo.clear();
+        self.committed = Bodies::of(world);
+        self.pending = None;
+    }
+    /// Names the edit in progress. The first name given since the last checkpoint sticks.
+    pub fn note(&mut self, what: &'static str) {
+        self.pending.get_or_insert(what);
+    }
+    /// Records any change since the last checkpoint as a single entry.
+    pub fn checkpoint(&mut self, world: &World) {
+        let what = self.pending.take().unwrap_or("Edit");
+        if self.committed.matches(world) { return }
+        let after = Bodies::of(world);
+        let before = std::mem::replace(&mut self.committed, after.clone());
+        self.undo.push(Entry { what, before, after });
+        if self.undo.len() > MAX_ENTRIES { self.undo.remove(0); }
+        self.redo.clear();
+    }
+    pub fn undo(&mut self, world: &mut World) -> bool {
+        self.checkpoint(world);
+        match self.undo.pop() {
+            Some(entry) => {
+                entry.before.restore(world);
+                self.committed = entry.before.clone();
+                self.redo.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn redo(&mut self, world: &mut World) -> bool {
+        self.checkpoint(world);
+        match self.redo.pop() {
+            Some(entry) => {
+                entry.after.restore(world);
+                self.committed = entry.after.clone();
+                self.undo.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+    /// What the next undo would take back, if anything, including edits not yet checkpointed.
+    pub fn next_undo(&self, world: &World) -> Option<&'static str> {
+        if !self.committed.matches(world) { return Some(self.pending.unwrap_or("Edit")) }
+        self.undo.last().map(|e| e.what)
+    }
+    pub fn next_redo(&self, world: &World) -> Option<&'static str> {
+        if !self.committed.matches(world) { return None }
+        self.redo.last().map(|e| e.what)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Presets;
+
+    #[test]
+    fn drags_coalesce_and_undo_redo_round_trip() {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        let mut history = History::default();
+        history.clear(&world);
+        let start = world.vertices.clone();
+
+        history.note("Move vertex");
+        for step in 0..10 {
+            world.vertices[0].x += step as f64;
+        }
+        world.vertices[0].selected = true;
+        history.checkpoint(&world);
+        let moved = world.vertices[0].x;
+        world.delete_vertex(1);
+        history.note("Delete");
+        history.checkpoint(&world);
+        history.checkpoint(&world);
+
+        assert_eq!(history.next_undo(&world), Some("Delete"));
+        assert!(history.undo(&mut world));
+        assert!(world.vertices[1].used);
+        assert_eq!(history.next_undo(&world), Some("Move vertex"));
+        assert!(history.undo(&mut world));
+        assert_eq!(world.vertices.iter().map(|v| v.x).collect::<Vec<_>>(), start.iter().map(|v| v.x).collect::<Vec<_>>());
+        assert!(!history.undo(&mut world));
+
+        assert!(history.redo(&mut world));
+        assert_eq!(world.vertices[0].x, moved);
+        world.add_vertex(10.0, 10.0, 0.0, 0.0, 0, 0.0, 0);
+        assert_eq!(history.next_redo(&world), None);
+        history.checkpoint(&world);
+        assert!(!history.redo(&mut world));
+    }
+
+    #[test]
+    fn selection_is_not_an_edit_but_keyframes_are() {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        let mut history = History::default();
+        history.clear(&world);
+        world.vertices[0].selected = true;
+        assert_eq!(history.next_undo(&world), None);
+        world.links[0].keyframes.push(crate::world::Keyframe { time: 10.0, extension: 2.0 });
+        assert_eq!(history.next_undo(&world), Some("Edit"));
+    }
+}
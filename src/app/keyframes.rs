@@ -0,0 +1,95 @@
+//! Editing a muscle's keyframes in the Muscles window: click the timeline to add one,
+//! drag one to move it, right-click one to remove it, or type them in under "Fine tuning".
+use super::timeline::{timeline_point, timeline_pos, TIMELINE_RANGE};
+use super::State;
+use crate::world::{Keyframe, Link};
+
+/// How close, in points, the pointer has to be to a keyframe to pick it up.
+const PICK_RADIUS: f32 = 6.0;
+
+/// Keyframes tracing out a link's single pulse in a cycle of `cycle` ticks, as a starting
+/// point for editing.
+fn pulse_keyframes(link: &Link, cycle: i32) -> Vec<Keyframe> {
+    if link.push_span <= 0 || link.push_strength == 0.0 {
+        return vec![Keyframe { time: 0.0, extension: 0.0 }]
+    }
+    let mut keys: Vec<Keyframe> = [-link.push_span, 0, link.push_span].iter().map(|&dt| Keyframe {
+        time: (link.push_timing + dt).rem_euclid(cycle) as f64,
+        extension: if dt == 0 { link.push_strength } else { 0.0 },
+    }).collect();
+    keys.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+    keys
+}
+
+impl State {
+    /// Switches link `n` from a single pulse to keyframes that match it.
+    pub(super) fn use_keyframes(&mut self, n: usize) {
+        self.world.links[n].keyframes = pulse_keyframes(&self.world.links[n], self.world.environment.cycle_length);
+        self.edited("Change muscle");
+    }
+    /// Handles the pointer on the Muscles window's timeline, `rect`, for link `n`.
+    pub(super) fn edit_keyframes(&mut self, ui: &egui::Ui, response: &egui::Response, rect: egui::Rect, n: usize) {
+        let cycle = self.world.environment.cycle_length as f64;
+        let near = |link: &Link, pos: egui::Pos2| {
+            link.keyframes.iter().position(|k| timeline_pos(rect, cycle, k.time, k.extension).distance(pos) < PICK_RADIUS)
+        };
+        if response.drag_started() {
+            self.drag_key = ui.input().pointer.press_origin().and_then(|pos| near(&self.world.links[n], pos));
+        }
+        if let Some(pos) = response.interact_pointer_pos() {
+            if response.clicked() && self.drag_key.is_none() {
+                let (time, extension) = timeline_point(rect, cycle, pos);
+                let link = &mut self.world.links[n];
+                link.keyframes.push(Keyframe { time: time.round().clamp(0.0, cycle), extension });
+                link.sort_keyframes();
+                self.edited("Add keyframe");
+            } else if let (Some(k), true) = (self.drag_key, response.dragged_by(egui::PointerButton::Primary) && response.drag_delta() != egui::Vec2::ZERO) {
+                // a keyframe can't be dragged past its neighbours, so they stay in order
+                let keys = &mut self.world.links[n].keyframes;
+                let earliest = if k > 0 { keys[k - 1].time } else { 0.0 };
+                let latest = keys.get(k + 1).map_or(cycle, |key| key.time);
+                let (time, extension) = timeline_point(rect, cycle, pos);
+                keys[k] = Keyframe { time: time.round().clamp(earliest, latest), extension: extension.clamp(-TIMELINE_RANGE, TIMELINE_RANGE) };
+                self.edited("Move keyframe");
+            }
+            if response.secondary_clicked() && self.world.links[n].keyframes.len() > 1 {
+                if let Some(k) = near(&self.world.links[n], pos) {
+                    self.world.links[n].keyframes.remove(k);
+                    self.edited("Remove keyframe");
+                }
+            }
+        }
+        if !response.dragged() { self.drag_key = None }
+    }
+    /// The keyframes of link `n`, under "Fine tuning" in the Muscles window.
+    pub(super) fn keyframe_list(&mut self, ui: &mut egui::Ui, n: usize) {
+        ui.label("Click the timeline to add a point, drag one to move it, right-click to remove it.");
+        let (mut changed, mut remove) = (false, None);
+        let cycle = self.world.environment.cycle_length as f64;
+        let keys = &mut self.world.links[n].keyframes;
+        let count = keys.len();
+        egui::Grid::new("keyframes").show(ui, |ui| {
+            ui.label("Time");
+            ui.label("Push");
+            ui.end_row();
+            for (k, key) in keys.iter_mut().enumerate() {
+                changed |= ui.add(egui::DragValue::new(&mut key.time).speed(1).clamp_range(0.0..=cycle)).changed();
+                changed |= ui.add(egui::DragValue::new(&mut key.extension).speed(0.1)).changed();
+                if count > 1 && ui.button("✖").on_hover_text("Remove").clicked() { remove = Some(k) }
+                ui.end_row();
+            }
+        });
+        if changed {
+            self.world.links[n].sort_keyframes();
+            self.edited("Move keyframe");
+        }
+        if let Some(k) = remove {
+            self.world.links[n].keyframes.remove(k);
+            self.edited("Remove keyframe");
+        }
+        if ui.button("Back to a single pulse").clicked() {
+            self.world.links[n].keyframes.clear();
+            self.edited("Change muscle");
+        }
+    }
+}
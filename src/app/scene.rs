@@ -0,0 +1,335 @@
+//! The versioned scene format. Unlike the legacy .botz records this keeps every
+//! field of the scene, and carries a version number so that files written by an
+//! older build can be migrated forward when the layout changes.
+use super::{DisplayOptions, State};
+use crate::world::{Environment, Link, MuscleGroup, Obstacle, ParseError, Vertex, Walls, DEFAULT_CYCLE_LENGTH, MAX_CYCLE_LENGTH, MIN_CYCLE_LENGTH};
+use serde::{Deserialize, Serialize};
+
+/// Bump this whenever the layout of `Scene` changes, and teach `migrate` how to
+/// bring the previous version forward.
+pub const SCENE_VERSION: u32 = 16;
+
+#[derive(Serialize, Deserialize)]
+struct Scene {
+    version: u32,
+    mode: u8,
+    auto_reverse_enabled: bool,
+    current_phase: u8,
+    environment: Environment,
+    walls: Walls,
+    display_options: DisplayOptions,
+    vertices: Vec<Vertex>,
+    links: Vec<Link>,
+    terrain: Vec<Obstacle>,
+    groups: Vec<MuscleGroup>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Json(serde_json::Error),
+    Legacy(ParseError),
+    MissingVersion,
+    UnsupportedVersion(u32),
+    DanglingLink(usize),
+    BadMass(usize),
+    BadKeyframe(usize),
+    BadCycleLength(i32),
+}
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Json(e) => write!(f, "{}", e),
+            SceneError::Legacy(e) => write!(f, "{}", e),
+            SceneError::MissingVersion => write!(f, "scene has no version number"),
+            SceneError::UnsupportedVersion(v) => write!(f, "scene version {} is not supported (this build reads up to {})", v, SCENE_VERSION),
+            SceneError::DanglingLink(i) => write!(f, "link {} refers to a vertex that does not exist", i),
+            SceneError::BadMass(i) => write!(f, "vertex {} has a mass that is not a positive number", i),
+            SceneError::BadKeyframe(i) => write!(f, "link {} has a keyframe at a time that is not a number", i),
+            SceneError::BadCycleLength(n) => write!(f, "a cycle of {} ticks is not between {} and {}", n, MIN_CYCLE_LENGTH, MAX_CYCLE_LENGTH),
+        }
+    }
+}
+impl std::error::Error for SceneError {}
+
+/// Rewrites a scene saved as version `from` into the current layout, one version at a time.
+fn migrate(value: &mut serde_json::Value, from: u32) -> Result<(), SceneError> {
+    match from {
+        SCENE_VERSION => Ok(()),
+        1 => {
+            // version 2 added collisions, which didn't exist before
+            value["environment"]["collisions"] = "Off".into();
+            migrate(value, 2)
+        }
+        2 => {
+            // version 3 added terrain, and a colour to draw it in
+            value["terrain"] = serde_json::json!([]);
+            value["display_options"]["terrain_color"] = serde_json::json!([110, 90, 70, 255]);
+            migrate(value, 3)
+        }
+        3 => {
+            // version 4 added pinned vertices
+            for vertex in value["vertices"].as_array_mut().into_iter().flatten() {
+                vertex["pinned"] = false.into();
+            }
+            migrate(value, 4)
+        }
+        4 => {
+            // version 5 gave vertices a mass, and an option to draw it
+            for vertex in value["vertices"].as_array_mut().into_iter().flatten() {
+                vertex["mass"] = 1.0.into();
+            }
+            value["display_options"]["show_mass"] = false.into();
+            migrate(value, 5)
+        }
+        5 => {
+            // version 6 added breakable links
+            value["environment"]["breakable_links"] = false.into();
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["break_strain"] = crate::world::DEFAULT_BREAK_STRAIN.into();
+            }
+            migrate(value, 6)
+        }
+        6 => {
+            // version 7 added ropes, struts and rigid links; everything before was a spring
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["kind"] = "Spring".into();
+            }
+            migrate(value, 7)
+        }
+        7 => {
+            // version 8 added link damping
+            value["environment"]["damping"] = 0.0.into();
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["damping"] = 0.0.into();
+            }
+            migrate(value, 8)
+        }
+        8 => {
+            // version 9 added motors to wheels
+            for vertex in value["vertices"].as_array_mut().into_iter().flatten() {
+                vertex["motor"] = serde_json::json!({ "kind": "Off", "torque": 0.5, "speed": 5.0, "cycle": false });
+            }
+            migrate(value, 9)
+        }
+        9 => {
+            // version 10 added a choice of integrator
+            value["environment"]["integrator"] = "Legacy".into();
+            value["environment"]["substeps"] = 1.into();
+            migrate(value, 10)
+        }
+        10 => {
+            // version 11 added muscle waveforms; they were all triangles before
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["waveform"] = "Triangle".into();
+            }
+            migrate(value, 11)
+        }
+        11 => {
+            // version 12 added muscle keyframes
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["keyframes"] = serde_json::json!([]);
+            }
+            migrate(value, 12)
+        }
+        12 => {
+            // version 13 added muting and soloing muscles
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                link["muted"] = false.into();
+                link["solo"] = false.into();
+            }
+            migrate(value, 13)
+        }
+        13 => {
+            // version 14 added muscle groups
+            value["groups"] = serde_json::json!([]);
+            migrate(value, 14)
+        }
+        14 => {
+            // version 15 made the cycle length configurable; it was always 200 ticks, and
+            // the clock speed, now fractional, reads the same as before
+            value["environment"]["cycle_length"] = DEFAULT_CYCLE_LENGTH.into();
+            migrate(value, 15)
+        }
+        15 => {
+            // version 16 stopped saving the editor's cached link midpoints
+            for link in value["links"].as_array_mut().into_iter().flatten() {
+                if let Some(link) = link.as_object_mut() {
+                    link.remove("mid_x");
+                    link.remove("mid_y");
+                }
+            }
+            migrate(value, 16)
+        }
+        v => Err(SceneError::UnsupportedVersion(v)),
+    }
+}
+
+impl State {
+    pub fn scene_serialize(&self) -> String {
+        let scene = Scene {
+            version: SCENE_VERSION,
+            mode: self.mode,
+            auto_reverse_enabled: self.world.auto_reverse_enabled,
+            current_phase: self.current_phase,
+            environment: self.world.environment.clone(),
+            walls: self.world.walls.clone(),
+            display_options: self.display_options.clone(),
+            vertices: self.world.vertices.clone(),
+            links: self.world.links.clone(),
+            terrain: self.world.terrain.clone(),
+            groups: self.world.groups.clone(),
+        };
+        serde_json::to_string_pretty(&scene).expect("scenes always serialize")
+    }
+    /// Reads either format. Anything that isn't a JSON object is taken to be a
+    /// legacy .botz file, which is how old files get migrated.
+    pub fn scene_parse(&mut self, file: &str) -> Result<(), SceneError> {
+        if !file.trim_start().starts_with('{') {
+            return self.legacy_parse(file).map_err(SceneError::Legacy);
+        }
+        let mut value: serde_json::Value = serde_json::from_str(file).map_err(SceneError::Json)?;
+        let version = value.get("version").and_then(|v| v.as_u64()).ok_or(SceneError::MissingVersion)?;
+        migrate(&mut value, version as u32)?;
+        let mut scene: Scene = serde_json::from_value(value).map_err(SceneError::Json)?;
+        if !(MIN_CYCLE_LENGTH..=MAX_CYCLE_LENGTH).contains(&scene.environment.cycle_length) {
+            return Err(SceneError::BadCycleLength(scene.environment.cycle_length));
+        }
+        for (i, link) in scene.links.iter().enumerate() {
+            if link.src >= scene.vertices.len() || link.dest >= scene.vertices.len() {
+                return Err(SceneError::DanglingLink(i));
+            }
+        }
+        if let Some(i) = scene.vertices.iter().position(|v| !(v.mass > 0.0 && v.mass.is_finite())) {
+            return Err(SceneError::BadMass(i));
+        }
+        if let Some(i) = scene.links.iter().position(|l| l.keyframes.iter().any(|k| !(k.time.is_finite() && k.extension.is_finite()))) {
+            return Err(SceneError::BadKeyframe(i));
+        }
+        // the muscles look keyframes up in time order, which a hand-edited file might not keep
+        for link in &mut scene.links {
+            link.sort_keyframes();
+        }
+        self.mode = scene.mode;
+        self.world.auto_reverse_enabled = scene.auto_reverse_enabled;
+        self.current_phase = scene.current_phase;
+        self.world.environment = scene.environment;
+        self.world.walls = scene.walls;
+        self.display_options = scene.display_options;
+        self.world.replace_bodies(scene.vertices, scene.links);
+        self.world.terrain = scene.terrain;
+        self.world.groups = scene.groups;
+        self.scene_replaced();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{make_start, Presets};
+
+    #[test]
+    fn scene_round_trip_presets() {
+        for preset in Presets::ALL.iter() {
+            let mut first = make_start();
+            first.load_presets(*preset).unwrap();
+            first.world.walls.ceiling = false;
+            first.display_options.zoom2x = true;
+            let text = first.scene_serialize();
+            let mut second = make_start();
+            second.scene_parse(&text).unwrap();
+            assert_eq!(first.world.vertices, second.world.vertices, "vertices differ for {:?}", preset);
+            assert_eq!(first.world.links, second.world.links, "links differ for {:?}", preset);
+            assert!(!second.world.walls.ceiling && second.display_options.zoom2x);
+            assert_eq!(text, second.scene_serialize());
+        }
+    }
+
+    #[test]
+    fn scene_parse_migrates_legacy_and_rejects_future_versions() {
+        let mut s = make_start();
+        s.scene_parse(Presets::Muscles.source()).unwrap();
+        assert_eq!(s.world.vertices.len(), 3);
+        let future = s.scene_serialize().replacen(&format!("\"version\": {}", SCENE_VERSION), "\"version\": 99", 1);
+        assert!(matches!(s.scene_parse(&future), Err(SceneError::UnsupportedVersion(99))));
+        assert_eq!(s.world.vertices.len(), 3);
+    }
+
+    #[test]
+    fn scene_parse_rejects_massless_vertices() {
+        let mut s = make_start();
+        s.load_presets(Presets::Walker).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&s.scene_serialize()).unwrap();
+        s.load_presets(Presets::Muscles).unwrap();
+        for mass in [0.0, -1.0].iter() {
+            value["vertices"][4]["mass"] = (*mass).into();
+            assert!(matches!(s.scene_parse(&value.to_string()), Err(SceneError::BadMass(4))));
+        }
+        assert_eq!(s.world.vertices.len(), 3);
+    }
+
+    #[test]
+    fn scene_parse_sorts_keyframes_and_rejects_bad_times() {
+        let mut s = make_start();
+        s.load_presets(Presets::Walker).unwrap();
+        let mut value: serde_json::Value = serde_json::from_str(&s.scene_serialize()).unwrap();
+        value["links"][3]["keyframes"] = serde_json::json!([{ "time": 150.0, "extension": 2.0 }, { "time": 20.0, "extension": -1.0 }]);
+        s.scene_parse(&value.to_string()).unwrap();
+        let times: Vec<f64> = s.world.links[3].keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![20.0, 150.0]);
+        // JSON has no NaN, so one saved from a run that blew up comes back as null, but .botz does
+        s.world.links[3].keyframes[0].time = f64::NAN;
+        assert!(matches!(s.scene_parse(&s.scene_serialize()), Err(SceneError::Json(_))));
+        let legacy = "VX0|Y0|;VX10|Y0|;LA1|B2|L10|XNaN|Z1|;";
+        assert!(matches!(s.scene_parse(legacy), Err(SceneError::Legacy(e)) if e.field == 'X'));
+        assert!(s.scene_parse(&legacy.replace("NaN", "inf")).is_err());
+    }
+
+    #[test]
+    fn scene_parse_rejects_cycle_lengths_out_of_range() {
+        let mut s = make_start();
+        let mut value: serde_json::Value = serde_json::from_str(&s.scene_serialize()).unwrap();
+        for length in [0, -200, MAX_CYCLE_LENGTH + 1].iter() {
+            value["environment"]["cycle_length"] = (*length).into();
+            assert!(matches!(s.scene_parse(&value.to_string()), Err(SceneError::BadCycleLength(n)) if n == *length));
+        }
+        assert_eq!(s.world.environment.cycle_length, DEFAULT_CYCLE_LENGTH);
+    }
+
+    #[test]
+    fn scene_migrates_version_1() {
+        let mut s = make_start();
+        let mut value: serde_json::Value = serde_json::from_str(&s.scene_serialize()).unwrap();
+        value["version"] = 1.into();
+        value["environment"].as_object_mut().unwrap().remove("collisions");
+        value["environment"].as_object_mut().unwrap().remove("breakable_links");
+        value["environment"].as_object_mut().unwrap().remove("damping");
+        value["environment"].as_object_mut().unwrap().remove("integrator");
+        value["environment"].as_object_mut().unwrap().remove("substeps");
+        value["environment"].as_object_mut().unwrap().remove("cycle_length");
+        value["environment"]["clock_speed"] = 3.into();
+        for link in value["links"].as_array_mut().unwrap() {
+            link.as_object_mut().unwrap().remove("break_strain");
+            link.as_object_mut().unwrap().remove("kind");
+            link.as_object_mut().unwrap().remove("damping");
+            link.as_object_mut().unwrap().remove("waveform");
+            link.as_object_mut().unwrap().remove("keyframes");
+            link.as_object_mut().unwrap().remove("muted");
+            link.as_object_mut().unwrap().remove("solo");
+        }
+        value.as_object_mut().unwrap().remove("terrain");
+        value.as_object_mut().unwrap().remove("groups");
+        value["display_options"].as_object_mut().unwrap().remove("terrain_color");
+        value["display_options"].as_object_mut().unwrap().remove("show_mass");
+        for vertex in value["vertices"].as_array_mut().unwrap() {
+            vertex.as_object_mut().unwrap().remove("pinned");
+            vertex.as_object_mut().unwrap().remove("mass");
+            vertex.as_object_mut().unwrap().remove("motor");
+        }
+        s.world.environment.collisions = crate::world::Collisions::All;
+        s.scene_parse(&value.to_string()).unwrap();
+        assert_eq!(s.world.environment.collisions, crate::world::Collisions::Off);
+        assert!(s.world.terrain.is_empty());
+        assert_eq!((s.world.environment.cycle_length, s.world.environment.clock_speed), (DEFAULT_CYCLE_LENGTH, 3.0));
+    }
+}
@@ -0,0 +1,222 @@
+//! The Muscles window's timelines: the large one, where the selected link's muscle is
+//! drawn and shaped, and below it a track for every muscle, where they can be compared,
+//! muted or soloed, and their pulses moved or resized, several at once if need be.
+use super::State;
+use crate::world::{Keyframe, Link};
+use egui::color::Color32;
+
+/// How far a muscle pushes at the very top (or, pulling, the bottom) of the main timeline.
+pub(super) const TIMELINE_RANGE: f64 = 20.0;
+const ROW_HEIGHT: f32 = 20.0;
+const LABEL_WIDTH: f32 = 52.0;
+/// How close, in points, to either end of a pulse a drag resizes it rather than moving it.
+const EDGE: f32 = 4.0;
+
+/// Where a point on a muscle's curve goes in `rect`, if it spans a cycle of `cycle` ticks
+/// and `range` reaches its top.
+fn curve_pos(rect: egui::Rect, cycle: f64, time: f64, extension: f64, range: f64) -> egui::Pos2 {
+    egui::Pos2::new(
+        rect.left() + (time / cycle) as f32 * rect.width(),
+        rect.center().y - (extension / range) as f32 * rect.height() / 2.0,
+    )
+}
+/// Where a point on a muscle's curve goes on the main timeline.
+pub(super) fn timeline_pos(rect: egui::Rect, cycle: f64, time: f64, extension: f64) -> egui::Pos2 {
+    curve_pos(rect, cycle, time, extension, TIMELINE_RANGE)
+}
+/// The time and extension at a point on the main timeline.
+pub(super) fn timeline_point(rect: egui::Rect, cycle: f64, pos: egui::Pos2) -> (f64, f64) {
+    (
+        ((pos.x - rect.left()) / rect.width()) as f64 * cycle,
+        ((rect.center().y - pos.y) / (rect.height() / 2.0)) as f64 * TIMELINE_RANGE,
+    )
+}
+/// The (time, extension) points a link's muscle passes through, left to right, or None if
+/// it has no muscle. They may run off either end of the cycle, which is `cycle` ticks long.
+pub(super) fn outline(link: &Link, cycle: f64) -> Option<Vec<(f64, f64)>> {
+    if let Some(&first) = link.keyframes.first() {
+        // from keyframe to keyframe, and from the last round to the first
+        let mut points: Vec<_> = link.keyframes.iter().map(|k| (k.time, k.extension)).collect();
+        points.push((first.time + cycle, first.extension));
+        return Some(points)
+    }
+    if link.push_span <= 0 || link.push_strength == 0.0 || link.push_timing as f64 > cycle { return None }
+    // trace the muscle's shape across its span
+    let steps = 32;
+    let mut points = vec![((link.push_timing - link.push_span) as f64, 0.0)];
+    for s in 0..=steps {
+        let t = s as f64 * 2.0 / steps as f64 - 1.0;
+        points.push((link.push_timing as f64 + t * link.push_span as f64, link.push_strength * link.waveform.level(t)));
+    }
+    points.push(((link.push_timing + link.push_span) as f64, 0.0));
+    Some(points)
+}
+/// Draws a line through `points`, and again a cycle either side so curves that wrap round
+/// the ends of `rect` show at both.
+pub(super) fn draw_outline(ui: &egui::Ui, rect: egui::Rect, points: &[egui::Pos2], color: Color32) {
+    let p = ui.painter_at(rect);
+    for &shift in &[-rect.width(), 0.0, rect.width()] {
+        for pair in points.windows(2) {
+            p.line_segment([pair[0] + egui::Vec2::new(shift, 0.0), pair[1] + egui::Vec2::new(shift, 0.0)], egui::Stroke::new(1.0, color));
+        }
+    }
+}
+/// The beat lines and the clock, behind the curves on either kind of timeline.
+pub(super) fn draw_grid(ui: &egui::Ui, rect: egui::Rect, cycle: f64, cycle_time: f64) {
+    let p = ui.painter_at(rect);
+    for &quarter in &[0.25, 0.5, 0.75] {
+        let x = curve_pos(rect, 1.0, quarter, 0.0, 1.0).x;
+        p.line_segment([egui::Pos2::new(x, rect.top()), egui::Pos2::new(x, rect.bottom())], egui::Stroke::new(1.0, Color32::LIGHT_GRAY));
+    }
+    p.line_segment([rect.left_center(), rect.right_center()], egui::Stroke::new(1.0, Color32::BLACK));
+    let x = curve_pos(rect, cycle, cycle_time, 0.0, 1.0).x;
+    p.line_segment([egui::Pos2::new(x, rect.top()), egui::Pos2::new(x, rect.bottom())], egui::Stroke::new(1.0, Color32::GOLD));
+}
+
+/// Which part of a pulse a track drag took hold of.
+#[derive(Copy, Clone, PartialEq)]
+enum Grab {
+    Move,
+    Start,
+    End,
+}
+/// A drag on the tracks, with where it began and how the muscles it moves were before it,
+/// so it can be worked out afresh each frame rather than piling up rounding.
+pub(super) struct TrackDrag {
+    grab: Grab,
+    from: f64,
+    originals: Vec<(usize, i32, i32, Vec<Keyframe>)>,
+}
+
+impl State {
+    /// Selects link `i`, as clicking it in the playfield would.
+    fn select_link(&mut self, i: usize) {
+        self.sel_link = Some(i);
+        self.drag_dot = None;
+        self.sel_vertex = None;
+        self.sub_mode_data = 0;
+        self.sub_mode = 4;
+        self.clear_multi_select();
+    }
+    /// A track for every muscle, under the main timeline.
+    pub(super) fn muscle_tracks(&mut self, ui: &mut egui::Ui) {
+        let links = self.world.links.len();
+        self.sel_muscles.retain(|&i| i < links);
+        let cycle = self.world.environment.cycle_length as f64;
+        let tracks: Vec<usize> = (0..links).filter(|&i| outline(&self.world.links[i], cycle).is_some()).collect();
+        if tracks.is_empty() {
+            ui.label("No muscles yet. Select a link and drag across the timeline to give it one.");
+            return
+        }
+        egui::ScrollArea::from_max_height(6.0 * (ROW_HEIGHT + ui.spacing().item_spacing.y)).show(ui, |ui| {
+            for i in tracks {
+                ui.horizontal(|ui| self.track(ui, i));
+            }
+        });
+    }
+    fn track(&mut self, ui: &mut egui::Ui, i: usize) {
+        let shift = ui.input().modifiers.shift;
+        let cycle = self.world.environment.cycle_length as f64;
+        let label = ui.add_sized([LABEL_WIDTH, ROW_HEIGHT], egui::SelectableLabel::new(self.sel_link == Some(i), format!("Link {}", i)));
+        if label.on_hover_text(self.group_name(self.world.links[i].phase)).clicked() {
+            self.click_track(i, shift);
+        }
+        if ui.selectable_label(self.world.links[i].solo, "S").on_hover_text("Solo: while any muscle is soloed, only soloed ones push").clicked() {
+            self.world.links[i].solo = !self.world.links[i].solo;
+            self.edited("Solo muscle");
+        }
+        if ui.selectable_label(self.world.links[i].muted, "M").on_hover_text("Mute").clicked() {
+            self.world.links[i].muted = !self.world.links[i].muted;
+            self.edited("Mute muscle");
+        }
+        let (lane, response) = ui.allocate_exact_size(egui::Vec2::new(ui.available_width(), ROW_HEIGHT), egui::Sense::click_and_drag());
+        let chosen = self.sel_muscles.contains(&i);
+        ui.painter().rect_filled(lane, 2.0, if chosen { Color32::from_rgb(225, 235, 250) } else { Color32::WHITE });
+        let clock = self.world.group_time(self.world.links[i].phase);
+        draw_grid(ui, lane, cycle, clock.unwrap_or(self.world.cycle_time));
+        let link = &self.world.links[i];
+        if let Some(points) = outline(link, cycle) {
+            // each track is scaled to its own muscle, so weak ones still show
+            let range = points.iter().fold(0.0f64, |m, &(_, e)| m.max(e.abs())).max(1e-9) * 1.2;
+            let points: Vec<_> = points.iter().map(|&(t, e)| curve_pos(lane, cycle, t, e, range)).collect();
+            let color = if link.muted || clock.is_none() { Color32::LIGHT_GRAY } else if self.sel_link == Some(i) { Color32::RED } else if chosen { Color32::BLUE } else { Color32::GRAY };
+            draw_outline(ui, lane, &points, color);
+        }
+
+        if response.drag_started() {
+            if let Some(pos) = ui.input().pointer.press_origin() {
+                if !shift && !chosen {
+                    self.sel_muscles = vec![i];
+                    self.select_link(i);
+                }
+                self.start_track_drag(i, timeline_point(lane, cycle, pos).0, lane.width());
+            }
+        }
+        if response.dragged() {
+            if let (Some(drag), Some(pos)) = (self.track_drag.take(), response.interact_pointer_pos()) {
+                self.drag_tracks(&drag, timeline_point(lane, cycle, pos).0);
+                self.track_drag = Some(drag);
+            }
+        }
+        if response.drag_released() { self.track_drag = None }
+        if response.clicked() { self.click_track(i, shift) }
+    }
+    /// Clicking a track selects its link, or with shift adds it to or takes it out of the
+    /// muscles that move together.
+    fn click_track(&mut self, i: usize, shift: bool) {
+        if !shift {
+            self.sel_muscles = vec![i];
+        } else if let Some(k) = self.sel_muscles.iter().position(|&j| j == i) {
+            self.sel_muscles.remove(k);
+        } else {
+            self.sel_muscles.push(i);
+        }
+        self.select_link(i);
+    }
+    /// Takes hold of link `i`'s pulse at `from` ticks, along with any other chosen muscles.
+    fn start_track_drag(&mut self, i: usize, from: f64, width: f32) {
+        let link = &self.world.links[i];
+        let grab = if link.keyframes.is_empty() {
+            // how far from the middle of the pulse, the shorter way round the cycle
+            let cycle = self.world.environment.cycle_length as f64;
+            let offset = (from - link.push_timing as f64 + cycle / 2.0).rem_euclid(cycle) - cycle / 2.0;
+            let edge = EDGE as f64 * cycle / width as f64;
+            if (offset.abs() - link.push_span as f64).abs() < edge {
+                if offset < 0.0 { Grab::Start } else { Grab::End }
+            } else if offset.abs() < link.push_span as f64 {
+                Grab::Move
+            } else {
+                return
+            }
+        } else {
+            Grab::Move
+        };
+        let mut moving = self.sel_muscles.clone();
+        if !moving.contains(&i) { moving.push(i) }
+        let originals = moving.into_iter().map(|j| {
+            let link = &self.world.links[j];
+            (j, link.push_timing, link.push_span, link.keyframes.clone())
+        }).collect();
+        self.track_drag = Some(TrackDrag { grab, from, originals });
+    }
+    fn drag_tracks(&mut self, drag: &TrackDrag, to: f64) {
+        let dt = (to - drag.from).round() as i32;
+        let cycle = self.world.environment.cycle_length;
+        let longest = (cycle / 2).max(1);
+        for (j, timing, span, keys) in &drag.originals {
+            let link = &mut self.world.links[*j];
+            match drag.grab {
+                Grab::Move => {
+                    link.push_timing = (timing + dt).rem_euclid(cycle);
+                    link.keyframes = keys.iter().map(|k| Keyframe { time: (k.time + dt as f64).rem_euclid(cycle as f64), ..*k }).collect();
+                    link.sort_keyframes();
+                }
+                // pulses are symmetrical, so either end pulls both
+                Grab::Start if keys.is_empty() => link.push_span = (span - dt).clamp(1, longest),
+                Grab::End if keys.is_empty() => link.push_span = (span + dt).clamp(1, longest),
+                _ => {}
+            }
+        }
+        self.edited(if drag.grab == Grab::Move { "Move muscle" } else { "Resize muscle" });
+    }
+}
@@ -0,0 +1,274 @@
+//! Runs a .botz creature without a window and reports how it got on, for
+//! regression tests in CI and overnight parameter sweeps.
+//!
+//!     botz-sim walker.botz --ticks 2000 --set gravity=0.3 --output walker.txt
+use botz_rs::world::{Collisions, Integrator, World, MAX_CYCLE_LENGTH, MIN_CYCLE_LENGTH};
+use std::fmt::Write;
+use std::process::exit;
+
+const USAGE: &str = "usage: botz-sim FILE.botz [--ticks N] [--width W] [--height H] [--set NAME=VALUE]... [--output FILE] [--save FILE] [--require-upright]
+
+  --ticks N          number of physics ticks to run (default 1000)
+  --width, --height  size of the playfield in pixels (default 800x600, like the GUI)
+  --set NAME=VALUE   override an environment setting: gravity, atmosphere, wall_bounce,
+                     wall_friction, left_wind, tension, damping, clock_speed, collisions
+                     (off, bodies or all), breakable_links (on or off), integrator
+                     (legacy, verlet or pbd), substeps or cycle_length (which stretches the
+                     muscles' timings to fit)
+  --output FILE      write the report to FILE instead of standard output
+  --save FILE        write the final state of the creature to FILE as .botz, leaving out
+                     anything the original VB program couldn't read
+  --require-upright  exit with status 2 if the creature fell over or blew up";
+
+/// A creature counts as having fallen over if its centre of mass ends up lower
+/// than this fraction of its height once it has settled.
+const FALLEN_RATIO: f64 = 0.5;
+/// How many ticks a creature gets to land and find its feet before its height is
+/// taken, so one placed in mid-air doesn't fall over just by dropping to the floor.
+/// Heights are averaged over this many ticks too, so one that bounces or jumps is
+/// judged on its posture rather than wherever a single tick caught it.
+const SETTLE_TICKS: usize = 100;
+
+struct Options {
+    file: String,
+    ticks: u32,
+    width: f64,
+    height: f64,
+    settings: Vec<(String, String)>,
+    output: Option<String>,
+    save: Option<String>,
+    require_upright: bool,
+    help: bool,
+}
+
+/// How a run went.
+struct Outcome {
+    report: String,
+    /// The .botz text of the creature as the run left it.
+    saved: String,
+    /// What the .botz text had to leave out.
+    lost: Vec<&'static str>,
+    fallen: bool,
+    /// The first tick after which a position or strain was no longer a number.
+    blew_up: Option<u32>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut args = args.into_iter();
+    let mut options = Options { file: String::new(), ticks: 1000, width: 800.0, height: 600.0, settings: Vec::new(), output: None, save: None, require_upright: false, help: false };
+    let mut file = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--ticks" => options.ticks = value(&arg)?.parse().map_err(|_| "--ticks needs a whole number".to_string())?,
+            "--width" => options.width = value(&arg)?.parse().map_err(|_| "--width needs a number".to_string())?,
+            "--height" => options.height = value(&arg)?.parse().map_err(|_| "--height needs a number".to_string())?,
+            "--set" => {
+                let setting = value(&arg)?;
+                let (name, v) = setting.split_once('=').ok_or(format!("--set expects NAME=VALUE, got {:?}", setting))?;
+                options.settings.push((name.to_string(), v.to_string()));
+            }
+            "--output" => options.output = Some(value(&arg)?),
+            "--save" => options.save = Some(value(&arg)?),
+            "--require-upright" => options.require_upright = true,
+            "-h" | "--help" => { options.help = true; return Ok(options) }
+            _ if arg.starts_with('-') => return Err(format!("unknown option {}", arg)),
+            _ if file.is_none() => file = Some(arg),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+    options.file = file.ok_or("no .botz file given")?;
+    Ok(options)
+}
+
+fn apply_setting(world: &mut World, name: &str, value: &str) -> Result<(), String> {
+    let bad = |_| format!("bad value {:?} for {}", value, name);
+    if name == "cycle_length" {
+        let length = value.parse().map_err(|_| format!("bad value {:?} for {}", value, name))?;
+        if !(MIN_CYCLE_LENGTH..=MAX_CYCLE_LENGTH).contains(&length) {
+            return Err(format!("{} must be between {} and {}", name, MIN_CYCLE_LENGTH, MAX_CYCLE_LENGTH));
+        }
+        world.set_cycle_length(length);
+        return Ok(())
+    }
+    let env = &mut world.environment;
+    match name {
+        "gravity" => env.gravity = value.parse().map_err(bad)?,
+        "atmosphere" => env.atmosphere = value.parse().map_err(bad)?,
+        "wall_bounce" => env.wall_bounce = value.parse().map_err(bad)?,
+        "wall_friction" => env.wall_friction = value.parse().map_err(bad)?,
+        "left_wind" => env.left_wind = value.parse().map_err(bad)?,
+        "tension" => env.tension = value.parse().map_err(bad)?,
+        "damping" => env.damping = value.parse().map_err(bad)?,
+        "clock_speed" => env.clock_speed = value.parse().map_err(bad)?,
+        "collisions" => env.collisions = match value {
+            "off" => Collisions::Off,
+            "bodies" => Collisions::BetweenBodies,
+            "all" => Collisions::All,
+            _ => return Err(format!("bad value {:?} for collisions (expected off, bodies or all)", value)),
+        },
+        "integrator" => env.integrator = match value {
+            "legacy" => Integrator::Legacy,
+            "verlet" => Integrator::Verlet,
+            "pbd" => Integrator::Pbd,
+            _ => return Err(format!("bad value {:?} for integrator (expected legacy, verlet or pbd)", value)),
+        },
+        "substeps" => env.substeps = value.parse().map_err(|_| format!("bad value {:?} for {}", value, name))?,
+        "breakable_links" => env.breakable_links = match value {
+            "on" => true,
+            "off" => false,
+            _ => return Err(format!("bad value {:?} for breakable_links (expected on or off)", value)),
+        },
+        _ => return Err(format!("unknown setting {}", name)),
+    }
+    Ok(())
+}
+
+/// Runs the creature in `text` as `options` say, without touching any files.
+fn simulate(options: &Options, text: &str) -> Result<Outcome, String> {
+    let mut world = World::default();
+    let mode = world.legacy_parse(text).map_err(|e| format!("{}: {}", options.file, e))?;
+    for (name, value) in &options.settings {
+        apply_setting(&mut world, name, value)?;
+    }
+    world.rightwall = options.width - 4.0;
+    world.ceiling = options.height - 4.0;
+
+    let start = world.centre_of_mass().ok_or(format!("{} has no vertices", options.file))?;
+    let mut heights = Vec::new();
+    let mut max_strain = (0.0, None);
+    let mut broken = 0;
+    let mut blew_up = None;
+    for tick in 1..=options.ticks {
+        world.step();
+        broken += world.broken.len();
+        for i in 0..world.links.len() {
+            let strain = world.link_strain(i);
+            if !strain.is_finite() {
+                blew_up = blew_up.or(Some(tick));
+            } else if strain.abs() > f64::abs(max_strain.0) {
+                max_strain = (strain, Some((i, tick)));
+            }
+        }
+        if world.vertices.iter().any(|v| v.used && !(v.x.is_finite() && v.y.is_finite())) {
+            blew_up = blew_up.or(Some(tick));
+        }
+        heights.push(world.centre_of_mass().map_or(start.1, |c| c.1));
+    }
+    let end = world.centre_of_mass().unwrap_or(start);
+    let mean = |heights: &[f64]| heights.iter().sum::<f64>() / heights.len() as f64;
+    // too short a run to have settled and then done anything gets no verdict
+    let settled = (heights.len() >= 2 * SETTLE_TICKS).then(|| mean(&heights[SETTLE_TICKS..2 * SETTLE_TICKS]));
+    let fallen = match settled {
+        Some(settled) => mean(&heights[heights.len() - SETTLE_TICKS..]) < settled * FALLEN_RATIO,
+        None => false,
+    };
+
+    let mut report = String::new();
+    let _ = writeln!(report, "file: {}", options.file);
+    let _ = writeln!(report, "ticks: {}", options.ticks);
+    let _ = writeln!(report, "centre of mass: ({:.3}, {:.3}) -> ({:.3}, {:.3})", start.0, start.1, end.0, end.1);
+    let _ = writeln!(report, "displacement: ({:.3}, {:.3})", end.0 - start.0, end.1 - start.1);
+    match max_strain {
+        (strain, Some((link, tick))) => { let _ = writeln!(report, "max link strain: {:.4} (link {} at tick {})", strain, link, tick); }
+        (_, None) => { let _ = writeln!(report, "max link strain: 0"); }
+    }
+    if world.environment.breakable_links { let _ = writeln!(report, "links broken: {}", broken); }
+    match blew_up {
+        Some(tick) => { let _ = writeln!(report, "blew up: yes (positions or strains stopped being numbers at tick {})", tick); }
+        None => { let _ = writeln!(report, "blew up: no"); }
+    }
+    if let Some(settled) = settled { let _ = writeln!(report, "settled height: {:.3} (ticks {} to {})", settled, SETTLE_TICKS + 1, 2 * SETTLE_TICKS); }
+    let _ = writeln!(report, "fell over: {}", if fallen { "yes" } else { "no" });
+    for (i, v) in world.vertices.iter().enumerate().filter(|(_, v)| v.used) {
+        let _ = writeln!(report, "vertex {}: ({:.3}, {:.3})", i, v.x, v.y);
+    }
+    Ok(Outcome { report, saved: world.legacy_serialize(mode), lost: world.legacy_losses(), fallen, blew_up })
+}
+
+/// The status to exit with after a run: 2 if it was meant to stay upright and didn't.
+fn exit_status(options: &Options, outcome: &Outcome) -> i32 {
+    if options.require_upright && (outcome.fallen || outcome.blew_up.is_some()) { 2 } else { 0 }
+}
+
+fn run(options: &Options) -> Result<i32, String> {
+    let text = std::fs::read_to_string(&options.file).map_err(|e| format!("could not read {}: {}", options.file, e))?;
+    let outcome = simulate(options, &text)?;
+    match &options.output {
+        Some(path) => std::fs::write(path, &outcome.report).map_err(|e| format!("could not write {}: {}", path, e))?,
+        None => print!("{}", outcome.report),
+    }
+    if let Some(path) = &options.save {
+        std::fs::write(path, &outcome.saved).map_err(|e| format!("could not write {}: {}", path, e))?;
+        if !outcome.lost.is_empty() {
+            eprintln!("botz-sim: warning: .botz files can't hold {}, so {} leaves them out", outcome.lost.join(", "), path);
+        }
+    }
+    Ok(exit_status(options, &outcome))
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) if options.help => { println!("{}", USAGE); exit(0) }
+        Ok(options) => options,
+        Err(e) => { eprintln!("botz-sim: {}\n\n{}", e, USAGE); exit(1) }
+    };
+    match run(&options) {
+        Ok(status) => exit(status),
+        Err(e) => { eprintln!("botz-sim: {}", e); exit(1) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        parse_args(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn walker_stays_upright_and_keeps_its_mode() {
+        let options = options(&["walker.botz", "--ticks", "600", "--require-upright"]);
+        let outcome = simulate(&options, include_str!("../../walker.botz")).unwrap();
+        assert!(outcome.report.contains("fell over: no\n"), "{}", outcome.report);
+        assert!(outcome.report.contains("blew up: no\n"), "{}", outcome.report);
+        assert_eq!(exit_status(&options, &outcome), 0);
+        assert_eq!(World::default().legacy_parse(&outcome.saved), Ok(1));
+        assert!(outcome.lost.is_empty());
+        // the unicycle file has no mode at all, and should come back that way
+        let outcome = simulate(&options, include_str!("../../unicycle.botz")).unwrap();
+        assert_eq!(World::default().legacy_parse(&outcome.saved), Ok(0));
+        // nor does the original format have anywhere to put collisions
+        let colliding = parse_args(["walker.botz", "--ticks", "10", "--set", "collisions=all"].iter().map(|arg| arg.to_string())).unwrap();
+        let outcome = simulate(&colliding, include_str!("../../walker.botz")).unwrap();
+        assert_eq!(outcome.lost, vec!["collisions"]);
+    }
+
+    #[test]
+    fn falling_over_and_blowing_up_fail_require_upright() {
+        let upright = options(&["dancer.botz", "--require-upright"]);
+        let outcome = simulate(&upright, include_str!("../../dancer.botz")).unwrap();
+        assert!(outcome.report.contains("fell over: yes\n"), "{}", outcome.report);
+        assert_eq!(exit_status(&upright, &outcome), 2);
+        assert_eq!(exit_status(&options(&["dancer.botz"]), &outcome), 0);
+        // two vertices on top of each other, joined by a link with no length
+        let text = "G0.49;A0.01;F0.5;B0.6;W0;T0.9;C4;M1;VX100|Y100|D1|H0|U0|C0|R0|P1|;VX100|Y100|D2|H0|U0|C0|R0|P1|;LA1|B2|L0|T0.9|S40|P0|N0|E0|M180|P1|;";
+        let outcome = simulate(&upright, text).unwrap();
+        assert_eq!(outcome.blew_up, Some(1));
+        assert!(outcome.report.contains("max link strain: 0\n"), "{}", outcome.report);
+        assert_eq!(exit_status(&upright, &outcome), 2);
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        assert!(parse_args(vec!["--ticks".to_string()]).is_err());
+        assert!(parse_args(vec!["a.botz".to_string(), "--set".to_string(), "gravity".to_string()]).is_err());
+        assert!(options(&["--help"]).help);
+        let mut world = World::default();
+        assert!(apply_setting(&mut world, "gravity", "up").is_err());
+        assert!(apply_setting(&mut world, "cycle_length", "0").is_err());
+        assert!(apply_setting(&mut world, "cycle_length", "100").is_ok());
+        assert_eq!(world.environment.cycle_length, 100);
+    }
+}
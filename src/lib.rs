@@ -1,4 +1,5 @@
 mod app;
+pub mod world;
 
 pub use app::make_start;
 #[cfg(target_arch = "wasm32")]
@@ -1,8 +1,6 @@
-mod app;
-
-pub use app::make_start;
+use botz_rs::make_start;
 extern crate eframe;
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
     eframe::run_native(Box::new(make_start()), Default::default())
-}
\ No newline at end of file
+}
@@ -0,0 +1,692 @@
+//! The simulation itself: bodies, the environment they live in, and the physics
+//! that moves them. Nothing in here knows about egui, so it can be stepped from
+//! tests, command-line tools or a server just as well as from the GUI.
+use serde::{Deserialize, Serialize};
+
+mod collision;
+mod diagnostics;
+mod integrator;
+mod legacy;
+mod motor;
+mod muscle;
+mod presets;
+mod replay;
+mod terrain;
+pub use collision::Collisions;
+pub use diagnostics::Diagnostics;
+pub use integrator::{Integrator, MAX_SUBSTEPS};
+pub use legacy::ParseError;
+pub use motor::{Motor, MotorKind};
+pub use muscle::{Keyframe, MuscleGroup, Waveform};
+pub use presets::Presets;
+pub use replay::{Player, Recorder, Replay, ReplayError};
+pub use terrain::Obstacle;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vertex {
+    pub used: bool,
+    pub just_released: bool,
+    pub selected: bool,
+    
+    pub x: f64,
+    pub y: f64,
+    pub last_x: f64,
+    pub last_y: f64, 
+    pub momentum_x: f64,
+    pub momentum_y: f64,
+    pub momentum_c: f64,
+    pub radius: u32, //should it be i64?
+    pub wheel: bool,
+    pub heading: f64,
+    pub phase: u8,
+    /// Fixed in place: the physics never moves it, though links and wheels still pull on it.
+    pub pinned: bool,
+    /// Relative to the default of 1. Heavier vertices give less when a link pulls on them,
+    /// or when they hit something, and are slowed less by the air and wind.
+    pub mass: f64,
+    /// Only does anything on a wheel.
+    pub motor: Motor,
+}
+impl Vertex {
+    /// How readily it moves when pushed or pulled: not at all if pinned.
+    pub fn inverse_mass(&self) -> f64 {
+        if self.pinned { 0.0 } else { 1.0 / self.mass }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Link {
+    //used: bool,
+    pub src: usize,
+    pub dest: usize,
+    pub length: f64,
+    pub tension: f64,
+    pub push_timing: i32,
+    pub push_span: i32,
+    pub push_strength: f64 ,
+    pub push: f64, 
+    pub last_len: f64,
+    pub phase : u8,
+    /// How far it can be stretched or squashed, as a fraction of its length, before it
+    /// snaps when `Environment::breakable_links` is on. Zero means it never breaks.
+    pub break_strain: f64,
+    pub kind: LinkKind,
+    /// How much of the ends' speed towards or away from each other it soaks up each tick.
+    pub damping: f64,
+    /// The shape of its muscle's push over the span.
+    pub waveform: Waveform,
+    /// Points its muscle passes through over the cycle, in order of time. When there are
+    /// any they replace the single pulse set by `push_timing`, `push_span` and `push_strength`.
+    pub keyframes: Vec<Keyframe>,
+    /// Its muscle is silenced.
+    pub muted: bool,
+    /// While any link is soloed, only soloed links' muscles push.
+    pub solo: bool,
+}
+/// How a link pulls its ends towards its length.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkKind {
+    /// Springs back whether stretched or squashed.
+    Spring,
+    /// Only pulls when stretched; goes slack when squashed.
+    Rope,
+    /// Only pushes when squashed; goes slack when stretched.
+    Strut,
+    /// Held at exactly its length after every tick.
+    Rigid,
+}
+/// How many passes are made over the rigid links each tick. Each pass fixes every link
+/// exactly but may upset its neighbours, so more passes stiffen chains and meshes.
+const RIGID_ITERATIONS: usize = 10;
+/// How many ticks one muscle cycle takes at a clock speed of 1, unless a scene says
+/// otherwise. Files from before `Environment::cycle_length` all assume this.
+pub const DEFAULT_CYCLE_LENGTH: i32 = 200;
+/// The shortest and longest cycles a scene may have. The timeline and keyframe editors
+/// wrap times round the cycle, so it can't be empty.
+pub const MIN_CYCLE_LENGTH: i32 = 10;
+pub const MAX_CYCLE_LENGTH: i32 = 2000;
+/// New links snap once they're half as long again as they should be, or half as short.
+pub const DEFAULT_BREAK_STRAIN: f64 = 0.5;
+/// A link that snapped during the last tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BrokenLink {
+    /// Where it was in `World::links` when it was removed.
+    pub index: usize,
+    pub link: Link,
+    pub strain: f64,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+    pub gravity : f64,
+    pub atmosphere : f64,
+    pub wall_bounce : f64,
+    pub wall_friction : f64,
+    pub left_wind : f64,
+    pub tension : f64,
+    /// The damping given to new links, as `tension` is their tension.
+    pub damping: f64,
+    /// How far the muscle clock moves each tick.
+    pub clock_speed : f64,
+    /// How many ticks of the muscle clock make up one cycle; muscle timings are all
+    /// somewhere between zero and this.
+    pub cycle_length: i32,
+    pub collisions: Collisions,
+    pub breakable_links: bool,
+    pub integrator: Integrator,
+    /// How many pieces each tick is split into, for the position-based integrators.
+    pub substeps: u32,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Walls{
+    pub ceiling: bool,
+    pub floor: bool,
+    pub left: bool,
+    pub right: bool,
+}
+pub struct World {
+    pub environment: Environment,
+    pub walls: Walls,
+    pub vertices: Vec<Vertex>,
+    pub links: Vec<Link>,
+    /// Static scenery, which the physics never moves.
+    pub terrain: Vec<Obstacle>,
+    /// Muscle groups, by the `phase` of the links in them.
+    pub groups: Vec<MuscleGroup>,
+    pub cycle_time: f64,
+    pub clock_pause: bool,
+    pub auto_reverse_cycle: i32,
+    pub auto_reverse_enabled: bool,
+    /// Where the right wall and ceiling sit; the floor and left wall are at zero.
+    pub rightwall: f64,
+    pub ceiling: f64,
+    /// A vertex being held in place by the user, which the physics leaves alone.
+    pub held: Option<usize>,
+    /// Links that snapped during the last call to `step`, in the order they were removed.
+    pub broken: Vec<BrokenLink>,
+    triangles_updated: bool,
+    triangles: std::collections::HashSet<(usize,usize,usize)>,
+}
+impl Default for World {
+    fn default() -> Self {
+        World {
+            environment: Environment {
+                atmosphere: 0.01,
+                clock_speed: 3.0,
+                cycle_length: DEFAULT_CYCLE_LENGTH,
+                gravity: 0.4,
+                left_wind: 0.0,
+                tension: 0.9,
+                damping: 0.0,
+                wall_bounce: 0.4,
+                wall_friction: 0.7,
+                collisions: Collisions::Off,
+                breakable_links: false,
+                integrator: Integrator::Legacy,
+                substeps: 1,
+            },
+            walls: Walls {
+                left: true,
+                right: true,
+                ceiling: true,
+                floor: true,
+            },
+            vertices: Vec::new(),
+            links: Vec::new(),
+            terrain: Vec::new(),
+            groups: Vec::new(),
+            cycle_time: 0.0,
+            clock_pause: false,
+            auto_reverse_cycle: 0,
+            auto_reverse_enabled: true,
+            rightwall: 797.0,
+            ceiling: 594.0,
+            held: None,
+            broken: Vec::new(),
+            triangles_updated: false,
+            triangles: std::collections::HashSet::new(),
+        }
+    }
+}
+impl World {
+    /// Swaps in a whole new set of bodies, e.g. after loading a file.
+    pub fn replace_bodies(&mut self, vertices: Vec<Vertex>, links: Vec<Link>) {
+        self.vertices = vertices;
+        self.links = links;
+        self.held = None;
+        self.triangles_updated = true;
+    }
+    /// Mass-weighted average position of the live vertices, or `None` if there aren't any.
+    pub fn centre_of_mass(&self) -> Option<(f64, f64)> {
+        let live = self.vertices.iter().filter(|v| v.used);
+        let (total, x, y) = live.fold((0.0, 0.0, 0.0), |(m, x, y), v| (m + v.mass, x + v.x * v.mass, y + v.y * v.mass));
+        if total == 0.0 { None } else { Some((x / total, y / total)) }
+    }
+    /// Halfway along a link, where the editor puts its handle.
+    pub fn link_midpoint(&self, link: usize) -> (f64, f64) {
+        let link = &self.links[link];
+        let (src, dest) = (&self.vertices[link.src], &self.vertices[link.dest]);
+        (dest.x + (src.x - dest.x) / 2.0, dest.y + (src.y - dest.y) / 2.0)
+    }
+    /// How far a link is from its rest length, as a fraction of that length.
+    /// Positive when stretched, negative when compressed. A link with no rest length
+    /// has no strain to speak of, so it gets zero rather than dividing by it.
+    pub fn link_strain(&self, link: usize) -> f64 {
+        let link = &self.links[link];
+        if link.length == 0.0 { return 0.0 }
+        let (src, dest) = (&self.vertices[link.src], &self.vertices[link.dest]);
+        let actual = ((dest.x - src.x).powi(2) + (dest.y - src.y).powi(2)).sqrt();
+        (actual - link.length) / link.length
+    }
+    /// Advances the simulation by one tick.
+    pub fn step(&mut self) {
+        let cycle_size = self.environment.cycle_length.max(1) as f64;
+        if !self.clock_pause  {
+            self.cycle_time += self.environment.clock_speed;
+            while self.cycle_time > cycle_size { self.cycle_time -= cycle_size };
+            while self.cycle_time < 0.0  { self.cycle_time += cycle_size}
+        }
+        self.update_muscles();
+        match self.environment.integrator {
+            Integrator::Legacy => self.step_legacy(),
+            integrator => self.step_positions(integrator),
+        }
+        self.break_links();
+    }
+    /// The original integrator: each link in turn nudges the momentum of its ends, then
+    /// every vertex moves by its momentum once. Kept exactly as it was so old creatures
+    /// behave as they always have; it ignores `Environment::substeps`.
+    fn step_legacy(&mut self) {
+        for i in 0..self.links.len() {
+            let link = &self.links[i];
+            let length_total = if self.clock_pause { link.length } else { link.length + link.push };
+            let t1 = self.vertices[link.src] ;
+            let t2 = self.vertices[link.dest];
+            // the correction is split by inverse mass, so the lighter end moves further
+            // and a pinned end not at all
+            let (w1, w2) = (t1.inverse_mass(), t2.inverse_mass());
+            let (share1, share2) = if w1 + w2 > 0.0 { (w1 / (w1 + w2), w2 / (w1 + w2)) } else { (0.0, 0.0) };
+            let xer = (t2.x + t2.momentum_x) - (t1.x + t1.momentum_x);
+            let yer = (t2.y + t2.momentum_y) - (t1.y + t1.momentum_y);
+            let leng = (xer * xer + yer * yer).abs().sqrt();
+            let leng2go_x = ((leng - length_total) / leng) * xer;
+            let leng2go_y = ((leng - length_total) / leng) * yer;
+            let slack = match link.kind {
+                LinkKind::Spring => false,
+                LinkKind::Rope => leng < length_total,
+                LinkKind::Strut => leng > length_total,
+                // solved separately once everything has moved
+                LinkKind::Rigid => true,
+            };
+            if slack { continue }
+            // damping pulls the ends' speeds along the link towards each other
+            let (damp_x, damp_y) = if link.damping != 0.0 && leng > 0.0 {
+                let (ux, uy) = (xer / leng, yer / leng);
+                let separating = (t2.momentum_x - t1.momentum_x) * ux + (t2.momentum_y - t1.momentum_y) * uy;
+                (separating * link.damping * ux, separating * link.damping * uy)
+            } else {
+                (0.0, 0.0)
+            };
+            { 
+                let t1 = & mut self.vertices[link.src];
+                t1.momentum_x += (leng2go_x * share1) * link.tension;
+                t1.momentum_y += (leng2go_y * share1) * link.tension;
+                if link.damping != 0.0 { t1.momentum_x += damp_x * share1; t1.momentum_y += damp_y * share1; }
+            }
+            {
+                let t2 = & mut self.vertices[link.dest];
+                t2.momentum_x -= (leng2go_x * share2) * link.tension;
+                t2.momentum_y -= (leng2go_y * share2) * link.tension;
+                if link.damping != 0.0 { t2.momentum_x -= damp_x * share2; t2.momentum_y -= damp_y * share2; }
+            }
+        }
+        let cycle = self.motor_cycle();
+        for i in 0..self.vertices.len() {
+            let vertex = &mut self.vertices[i];
+            if !vertex.used { continue; }
+            // gravity speeds everything up alike, whatever its mass; wind and drag are forces,
+            // so they move light vertices more than heavy ones
+            vertex.momentum_y -= self.environment.gravity * 1.5;
+            if vertex.just_released { vertex.momentum_x = 0.0; vertex.momentum_y = 0.0; vertex.just_released = false }
+            vertex.momentum_x += self.environment.left_wind / 10.0 / vertex.mass;
+            let drag = (self.environment.atmosphere / vertex.mass).min(1.0);
+            vertex.momentum_x *= 1.0 - drag;
+            vertex.momentum_y *= 1.0 - drag;
+            if self.held == Some(i) || vertex.pinned {
+                vertex.momentum_x = 0.0;
+                vertex.momentum_y = 0.0;
+            }
+            vertex.last_x = vertex.x;
+            vertex.last_y = vertex.y;
+            vertex.x += vertex.momentum_x;
+            vertex.y += vertex.momentum_y;
+            self.touch_walls(i, 1.0, cycle);
+        }
+        self.relax_rigid_links();
+        self.collide_terrain(1.0);
+        self.collide();
+    }
+    /// Keeps vertex `i` inside the walls that are turned on, bouncing it off them, and turns
+    /// its wheel. `h` is the fraction of a tick being simulated.
+    fn touch_walls(&mut self, i: usize, h: f64, cycle: f64) {
+        let vertex = &mut self.vertices[i];
+        // this shouldn't be needed            
+        vertex.wheel = vertex.radius > 0;
+        let fric = if vertex.wheel { 0.0 } else { self.environment.wall_friction };
+        // a motor pushing against a surface drives the wheel along it; in the air it only spins
+        let mut touching = false;
+        // TODO: enable options to toggle walls
+        if self.walls.floor && vertex.y - (vertex.radius as f64) < 0.1 { // floor
+            
+            vertex.y = vertex.radius as f64;
+            vertex.momentum_x *= 1.0 - fric * h;
+            vertex.momentum_y = -(vertex.momentum_y * self.environment.wall_bounce);
+            if vertex.wheel {
+                vertex.momentum_x += vertex.motor.drive(vertex.momentum_x, cycle) * h;
+                vertex.momentum_c = vertex.momentum_x;
+                touching = true;
+            }
+        }
+        if self.walls.left && vertex.x - (vertex.radius as f64) < 0.1 { // left wall
+            vertex.x = vertex.radius as f64;
+            vertex.momentum_y *= 1.0 - fric * h;
+            vertex.momentum_x = -(vertex.momentum_x * self.environment.wall_bounce);
+            if vertex.wheel {
+                vertex.momentum_y -= vertex.motor.drive(-vertex.momentum_y, cycle) * h;
+                vertex.momentum_c = -vertex.momentum_y;
+                touching = true;
+            }
+            if self.auto_reverse_enabled {
+                if self.auto_reverse_cycle == 0 { self.auto_reverse_cycle = 2; self.environment.clock_speed *= -1.0; };
+                if self.auto_reverse_cycle == 1 { self.auto_reverse_cycle = 2; self.environment.clock_speed *= -1.0; };
+            }
+        }
+        if self.walls.right && vertex.x + (vertex.radius as f64) > self.rightwall - 0.1 { // right wall
+            vertex.x = self.rightwall - vertex.radius as f64;
+            vertex.momentum_y *= 1.0 - fric * h;
+            vertex.momentum_x = -(vertex.momentum_x * self.environment.wall_bounce);
+            if vertex.wheel {
+                vertex.momentum_y += vertex.motor.drive(vertex.momentum_y, cycle) * h;
+                vertex.momentum_c = vertex.momentum_y;
+                touching = true;
+            }
+            if self.auto_reverse_enabled {
+                if self.auto_reverse_cycle == 0 { self.auto_reverse_cycle = 1; self.environment.clock_speed *= -1.0; };
+                if self.auto_reverse_cycle == 2 { self.auto_reverse_cycle = 1; self.environment.clock_speed *= -1.0; };
+            }
+        }
+        if self.walls.ceiling && vertex.y + (vertex.radius as f64) > self.ceiling - 0.1 {
+            vertex.y = self.ceiling - vertex.radius as f64;
+            vertex.momentum_x *= 1.0 - fric * h;
+            vertex.momentum_y = -(vertex.momentum_y * self.environment.wall_bounce);
+            if vertex.wheel {
+                vertex.momentum_x -= vertex.motor.drive(-vertex.momentum_x, cycle) * h;
+                vertex.momentum_c = -vertex.momentum_x;
+                touching = true;
+            }
+        }
+        if vertex.wheel && !touching { vertex.momentum_c += vertex.motor.drive(vertex.momentum_c, cycle) * h }
+        vertex.heading += vertex.momentum_c * h;
+        if vertex.heading > 360.0 { vertex.heading -= 360.0 };
+        if vertex.heading < 0.0 { vertex.heading += 360.0 };
+    }
+    /// Moves the ends of each rigid link to exactly its length, sharing the move by inverse
+    /// mass, and adds the move to their momentum so they carry on from where they ended up.
+    fn relax_rigid_links(&mut self) {
+        if !self.links.iter().any(|l| l.kind == LinkKind::Rigid) { return }
+        for _ in 0..RIGID_ITERATIONS {
+            for link in &self.links {
+                if link.kind != LinkKind::Rigid { continue }
+                let (t1, t2) = (self.vertices[link.src], self.vertices[link.dest]);
+                let held = |i| if self.held == Some(i) { 0.0 } else { 1.0 };
+                let (w1, w2) = (t1.inverse_mass() * held(link.src), t2.inverse_mass() * held(link.dest));
+                let (xer, yer) = (t2.x - t1.x, t2.y - t1.y);
+                let leng = (xer * xer + yer * yer).sqrt();
+                if w1 + w2 == 0.0 || leng == 0.0 { continue }
+                let target = if self.clock_pause { link.length } else { link.length + link.push };
+                let (fix_x, fix_y) = ((leng - target) / leng * xer / (w1 + w2), (leng - target) / leng * yer / (w1 + w2));
+                let t1 = &mut self.vertices[link.src];
+                t1.x += fix_x * w1; t1.y += fix_y * w1;
+                t1.momentum_x += fix_x * w1; t1.momentum_y += fix_y * w1;
+                let t2 = &mut self.vertices[link.dest];
+                t2.x -= fix_x * w2; t2.y -= fix_y * w2;
+                t2.momentum_x -= fix_x * w2; t2.momentum_y -= fix_y * w2;
+            }
+        }
+    }
+    /// Removes any link strained past its limit, measured against the length its muscle
+    /// is currently asking for rather than its rest length.
+    fn break_links(&mut self) {
+        self.broken.clear();
+        if !self.environment.breakable_links { return }
+        for i in (0..self.links.len()).rev() {
+            let link = &self.links[i];
+            if link.break_strain <= 0.0 { continue }
+            let (src, dest) = (&self.vertices[link.src], &self.vertices[link.dest]);
+            let actual = ((dest.x - src.x).powi(2) + (dest.y - src.y).powi(2)).sqrt();
+            let target = if self.clock_pause { link.length } else { link.length + link.push };
+            // a link squeezed down to nothing has no strain to measure, as in `link_strain`
+            if target <= 0.0 { continue }
+            let strain = (actual - target) / target;
+            if strain.abs() > link.break_strain {
+                let link = self.links.remove(i);
+                self.broken.push(BrokenLink { index: i, link, strain });
+                self.triangles_updated = true;
+            }
+        }
+        // removed from the end backwards so the indices stay meaningful; report them in the usual order
+        self.broken.reverse();
+    }
+    pub fn find_triangles(&mut self)   {
+        if !self.triangles_updated {
+            return
+        }
+        let mut adj: Vec<Vec<bool>> = vec![vec![false;self.vertices.len()]; self.vertices.len()];
+        let mut found : std::collections::HashSet<(usize,usize,usize)> = std::collections::HashSet::new();
+        for i in 0..self.links.len() {
+            adj[self.links[i].src][self.links[i].dest] = true;
+            adj[self.links[i].dest][self.links[i].src] = true;
+        }
+        for link in &self.links {
+            for (j, (&to_src, &to_dest)) in adj[link.src].iter().zip(&adj[link.dest]).enumerate() {
+                if j != link.src && j != link.dest && to_src && to_dest {
+                    if j < link.src && link.src < link.dest {
+                        found.insert((j,link.src,link.dest));
+                    } else if j < link.dest && link.dest < link.src {
+                        found.insert((j,link.dest,link.src));
+                    } else if link.src < j && j < link.dest {
+                        found.insert((link.src,j,link.dest));
+                    } else if link.dest < j && j < link.src {
+                        found.insert((link.dest,j,link.src));
+                    } else if link.dest < link.src && link.src < j {
+                        found.insert((link.dest,link.src, j));
+                    } else {
+                        found.insert((link.src,link.dest, j));
+                    }
+                }
+            }
+        }
+        self.triangles = found;
+        self.triangles_updated = false;
+    }
+    /// The triangles as of the last call to `find_triangles`.
+    pub fn triangles(&self) -> &std::collections::HashSet<(usize,usize,usize)> {
+        &self.triangles
+    }
+    pub fn reset_all_links(&mut self) {
+        for i in 0..self.links.len() {
+            self.reset_link(i);
+        }
+    }
+    pub fn reset_link(&mut self, link: usize) {
+        let t1 = self.vertices[self.links[link].src];
+        let t2 = self.vertices[self.links[link].dest];        
+        let xer = t2.x - t1.x;
+        let yer = t2.y - t1.y;
+        self.links[link].length = ((xer * xer + yer * yer).abs()).sqrt();
+    }
+
+    pub fn set_wheel(&mut self, vertex: usize, radius: u32) {
+        self.vertices[vertex].radius = radius;
+        self.vertices[vertex].wheel = radius > 0;
+        self.vertices[vertex].heading = 0.0;
+    }
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_vertex(&mut self, x: f64, y: f64, momentum_x: f64, momentum_y: f64, radius: u32, momentum_c: f64, phase: u8) -> usize {
+        
+        let vertex = Vertex {
+            x,y,momentum_c,momentum_x,momentum_y,radius,phase,
+            heading:0.0, wheel: radius > 0, just_released:false, last_x:0.0,last_y:0.0, selected:false,
+            used:true, pinned: false, mass: 1.0, motor: Motor::default(),
+        };
+        for i in 0..self.vertices.len() {
+            if !self.vertices[i].used {
+                self.vertices[i] = vertex;
+                return i;
+            }
+        }
+        self.triangles_updated = true;
+        self.vertices.push(vertex);
+        self.vertices.len() - 1
+    }
+    pub fn delete_link(&mut self, id: usize) {
+        self.links.remove(id);
+        self.triangles_updated = true;
+    }
+    /// Deleted vertices stay in place marked unused, so that the indices of the others don't shift.
+    pub fn delete_vertex(&mut self, id: usize) {
+        if self.held == Some(id) { self.held = None }
+        let mut i = 0;
+        while i != self.links.len() {
+            if  self.links[i].src == id || self.links[i].dest == id {
+                self.links.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        self.vertices[id].used = false;
+        self.triangles_updated = true;
+    }
+    pub fn add_link(&mut self, src: usize, dest: usize, phase: u8) -> bool {
+        if src == dest { return false };
+        for link in &self.links {
+            if link.src == src && link.dest == dest { return false };
+            if link.dest == src && link.src == dest { return false };
+        }
+        let x_len = self.vertices[dest].x - self.vertices[src].x;
+        let y_len = self.vertices[dest].y - self.vertices[src].y;
+        let len = (x_len * x_len + y_len * y_len).sqrt();
+        let link = Link {
+            src,
+            dest,
+            tension: self.environment.tension,
+            length: len,
+            last_len: 0.0,
+            phase,
+            push_strength: 0.0,
+            push_timing: 180,
+            push_span: 40,
+            push: 0.0,
+            break_strain: DEFAULT_BREAK_STRAIN,
+            kind: LinkKind::Spring,
+            damping: self.environment.damping,
+            waveform: Waveform::Triangle,
+            keyframes: Vec::new(),
+            muted: false,
+            solo: false,
+        };
+        
+        self.links.push(link);
+        self.triangles_updated = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Presets;
+
+    #[test]
+    fn walker_steps_headless() {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        for _ in 0..1000 {
+            world.step();
+        }
+        for v in &world.vertices {
+            assert!(v.x.is_finite() && v.y.is_finite());
+            assert!(v.x >= 0.0 && v.x <= world.rightwall && v.y >= 0.0 && v.y <= world.ceiling);
+        }
+    }
+    #[test]
+    fn pinned_vertex_holds_a_pendulum() {
+        let mut world = World::default();
+        let pivot = world.add_vertex(300.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let bob = world.add_vertex(400.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(pivot, bob, 0);
+        world.vertices[pivot].pinned = true;
+        let mut lowest: f64 = 300.0;
+        for _ in 0..500 {
+            world.step();
+            lowest = lowest.min(world.vertices[bob].y);
+        }
+        assert_eq!((world.vertices[pivot].x, world.vertices[pivot].y), (300.0, 300.0));
+        assert!(lowest < 220.0 && lowest > 150.0, "{}", lowest);
+        let (p, b) = (world.vertices[pivot], world.vertices[bob]);
+        assert!((((b.x - p.x).powi(2) + (b.y - p.y).powi(2)).sqrt() - 100.0).abs() < 20.0);
+    }
+    #[test]
+    fn links_pull_the_lighter_end_further() {
+        let mut world = World::default();
+        world.environment.gravity = 0.0;
+        world.environment.atmosphere = 0.0;
+        let heavy = world.add_vertex(300.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let light = world.add_vertex(400.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(heavy, light, 0);
+        world.links[0].length = 50.0;
+        world.vertices[heavy].mass = 4.0;
+        let before = world.centre_of_mass().unwrap();
+        world.step();
+        let (h, l) = (world.vertices[heavy], world.vertices[light]);
+        assert!(((400.0 - l.x) - 4.0 * (h.x - 300.0)).abs() < 1e-9, "{:?} {:?}", h, l);
+        let after = world.centre_of_mass().unwrap();
+        assert!((after.0 - before.0).abs() < 1e-9 && (after.1 - before.1).abs() < 1e-9);
+    }
+    #[test]
+    fn overstretched_links_snap_only_when_breakable() {
+        let mut world = World::default();
+        world.environment.gravity = 0.0;
+        let a = world.add_vertex(100.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(200.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let c = world.add_vertex(300.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        world.add_link(b, c, 0);
+        // a slack link doesn't pull back, so it stays stretched
+        world.links[1].tension = 0.0;
+        world.vertices[c].x = 400.0;
+        world.step();
+        assert_eq!(world.links.len(), 2);
+        assert!(world.broken.is_empty());
+
+        world.environment.breakable_links = true;
+        world.step();
+        assert_eq!(world.links.len(), 1);
+        assert_eq!(world.broken.len(), 1);
+        assert_eq!((world.broken[0].index, world.broken[0].link.dest), (1, c));
+        assert!(world.broken[0].strain > 0.5);
+        world.step();
+        assert!(world.broken.is_empty());
+        // nor does one with no length, however far apart its ends are
+        world.links[0].length = 0.0;
+        world.step();
+        assert_eq!(world.links.len(), 1);
+        assert!(world.broken.is_empty());
+    }
+    #[test]
+    fn ropes_go_slack_and_rigid_links_hold_their_length() {
+        let mut world = World::default();
+        world.environment.gravity = 0.0;
+        let a = world.add_vertex(100.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(200.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        world.links[0].kind = LinkKind::Rope;
+        world.links[0].length = 150.0;
+        world.step();
+        assert_eq!((world.vertices[a].x, world.vertices[b].x), (100.0, 200.0));
+        world.links[0].kind = LinkKind::Strut;
+        world.step();
+        assert!(world.vertices[b].x - world.vertices[a].x > 100.0);
+
+        let mut world = World::default();
+        let pivot = world.add_vertex(300.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        let bob = world.add_vertex(400.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(pivot, bob, 0);
+        world.links[0].kind = LinkKind::Rigid;
+        world.vertices[pivot].pinned = true;
+        for _ in 0..200 {
+            world.step();
+            let (p, b) = (world.vertices[pivot], world.vertices[bob]);
+            assert!((((b.x - p.x).powi(2) + (b.y - p.y).powi(2)).sqrt() - 100.0).abs() < 1e-9);
+        }
+    }
+    #[test]
+    fn damped_links_stop_bouncing() {
+        let wobble = |damping: f64| {
+            let mut world = World::default();
+            world.environment.gravity = 0.0;
+            world.environment.atmosphere = 0.0;
+            world.environment.damping = damping;
+            let a = world.add_vertex(100.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+            let b = world.add_vertex(200.0, 300.0, 0.0, 0.0, 0, 0.0, 0);
+            world.add_link(a, b, 0);
+            // a soft spring rings for a long time on its own
+            world.links[0].tension = 0.02;
+            world.vertices[b].momentum_x = 20.0;
+            for _ in 0..200 {
+                world.step();
+            }
+            (world.vertices[b].momentum_x - world.vertices[a].momentum_x).abs()
+        };
+        assert!(wobble(0.0) > 1.0);
+        assert!(wobble(0.2) < 0.01);
+    }
+}
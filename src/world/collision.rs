@@ -0,0 +1,220 @@
+//! Collisions between vertices, and between vertices and links. Wheels are circles of
+//! their own radius and plain vertices are small points; links are bare segments. Pairs
+//! are found with a uniform grid so the cost grows with the number of vertices rather
+//! than its square. Cells are only ever looked up, never iterated, so the order in which
+//! contacts are resolved is the same every run and replays stay exact.
+use super::World;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Collisions {
+    Off,
+    /// Separate creatures bump into each other, but each passes through itself.
+    BetweenBodies,
+    /// Parts of the same creature collide too, apart from vertices joined by a link.
+    All,
+}
+
+/// How big a plain vertex is for collisions.
+const POINT_RADIUS: f64 = 2.0;
+/// The grid is never finer than this, however small the vertices.
+const MIN_CELL: f64 = 16.0;
+/// Anything spanning more cells than this is checked against everything instead.
+const MAX_SPAN: i64 = 1024;
+
+struct Grid {
+    cell: f64,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    everywhere: Vec<usize>,
+}
+impl Grid {
+    fn key(&self, x: f64, y: f64) -> (i32, i32) {
+        ((x / self.cell).floor() as i32, (y / self.cell).floor() as i32)
+    }
+    fn insert_box(&mut self, (x0, y0): (f64, f64), (x1, y1): (f64, f64), item: usize) {
+        let (a, b) = (self.key(x0, y0), self.key(x1, y1));
+        if (b.0 as i64 - a.0 as i64 + 1) * (b.1 as i64 - a.1 as i64 + 1) > MAX_SPAN {
+            self.everywhere.push(item);
+            return
+        }
+        for cx in a.0..=b.0 {
+            for cy in a.1..=b.1 {
+                self.cells.entry((cx, cy)).or_default().push(item);
+            }
+        }
+    }
+    fn near(&self, x: f64, y: f64) -> impl Iterator<Item = usize> + '_ {
+        let (cx, cy) = self.key(x, y);
+        (cx.saturating_sub(1)..=cx.saturating_add(1)).flat_map(move |x| (cy.saturating_sub(1)..=cy.saturating_add(1)).map(move |y| (x, y)))
+            .filter_map(move |k| self.cells.get(&k)).flatten().chain(&self.everywhere).copied()
+    }
+}
+
+impl World {
+    fn collision_radius(&self, v: usize) -> f64 {
+        (self.vertices[v].radius as f64).max(POINT_RADIUS)
+    }
+    /// Which connected body each vertex belongs to, as the smallest vertex index in it.
+    fn bodies(&self) -> Vec<usize> {
+        let mut parent: Vec<usize> = (0..self.vertices.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i { parent[i] = parent[parent[i]]; i = parent[i] }
+            i
+        }
+        for link in &self.links {
+            let (a, b) = (root(&mut parent, link.src), root(&mut parent, link.dest));
+            parent[a.max(b)] = a.min(b);
+        }
+        (0..parent.len()).map(|i| root(&mut parent, i)).collect()
+    }
+    /// Pushes apart anything overlapping and bounces the momenta off each other, using the
+    /// environment's wall bounce and friction. Wheels are frictionless here, as against walls.
+    pub(super) fn collide(&mut self) {
+        if self.environment.collisions == Collisions::Off { return }
+        let live: Vec<usize> = (0..self.vertices.len()).filter(|&i| self.vertices[i].used && self.vertices[i].x.is_finite() && self.vertices[i].y.is_finite()).collect();
+        if live.is_empty() { return }
+        let body = self.bodies();
+        let same_body_ok = self.environment.collisions == Collisions::All;
+        let biggest = live.iter().map(|&i| self.collision_radius(i)).fold(0.0, f64::max);
+        let mut grid = Grid { cell: (2.0 * biggest).max(MIN_CELL), cells: HashMap::new(), everywhere: Vec::new() };
+        for &i in &live {
+            let v = &self.vertices[i];
+            grid.insert_box((v.x, v.y), (v.x, v.y), i);
+        }
+        let mut link_grid = Grid { cell: grid.cell, cells: HashMap::new(), everywhere: Vec::new() };
+        for (l, link) in self.links.iter().enumerate() {
+            let (a, b) = (&self.vertices[link.src], &self.vertices[link.dest]);
+            if !(a.x.is_finite() && a.y.is_finite() && b.x.is_finite() && b.y.is_finite()) { continue }
+            link_grid.insert_box((a.x.min(b.x), a.y.min(b.y)), (a.x.max(b.x), a.y.max(b.y)), l);
+        }
+        let linked: std::collections::HashSet<(usize, usize)> = self.links.iter().map(|l| (l.src.min(l.dest), l.src.max(l.dest))).collect();
+        let bounce = self.environment.wall_bounce;
+        let friction = self.environment.wall_friction.clamp(0.0, 1.0);
+
+        let mut seen = vec![usize::MAX; self.links.len()];
+        for &i in &live {
+            let (x, y) = (self.vertices[i].x, self.vertices[i].y);
+            let near: Vec<usize> = grid.near(x, y).filter(|&j| j > i).collect();
+            for j in near {
+                if body[i] == body[j] && (!same_body_ok || linked.contains(&(i, j))) { continue }
+                self.vertex_contact(i, j, bounce, friction);
+            }
+            let near: Vec<usize> = link_grid.near(x, y).collect();
+            for l in near {
+                if seen[l] == i { continue }
+                seen[l] = i;
+                let (src, dest) = (self.links[l].src, self.links[l].dest);
+                if src == i || dest == i || !self.vertices[src].used || !self.vertices[dest].used { continue }
+                if body[i] == body[src] && !same_body_ok { continue }
+                self.link_contact(i, src, dest, bounce, friction);
+            }
+        }
+    }
+    fn vertex_contact(&mut self, i: usize, j: usize, bounce: f64, friction: f64) {
+        let reach = self.collision_radius(i) + self.collision_radius(j);
+        let (a, b) = (self.vertices[i], self.vertices[j]);
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let d = (dx * dx + dy * dy).sqrt();
+        if d >= reach || d == 0.0 { return }
+        let (wa, wb) = (a.inverse_mass(), b.inverse_mass());
+        let share = wa + wb;
+        if share == 0.0 { return }
+        let (nx, ny) = (dx / d, dy / d);
+        let push = (reach - d) / share;
+        let fric = if a.wheel || b.wheel { 0.0 } else { friction };
+        let (jn, jt) = contact_impulse(b.momentum_x - a.momentum_x, b.momentum_y - a.momentum_y, nx, ny, bounce, fric, share);
+        let (ix, iy) = (jn * nx - jt * ny, jn * ny + jt * nx);
+        let a = &mut self.vertices[i];
+        a.x -= wa * push * nx; a.y -= wa * push * ny;
+        a.momentum_x -= wa * ix; a.momentum_y -= wa * iy;
+        let b = &mut self.vertices[j];
+        b.x += wb * push * nx; b.y += wb * push * ny;
+        b.momentum_x += wb * ix; b.momentum_y += wb * iy;
+    }
+    fn link_contact(&mut self, i: usize, src: usize, dest: usize, bounce: f64, friction: f64) {
+        let reach = self.collision_radius(i);
+        let (v, p, q) = (self.vertices[i], self.vertices[src], self.vertices[dest]);
+        let (ex, ey) = (q.x - p.x, q.y - p.y);
+        let len2 = ex * ex + ey * ey;
+        if len2 == 0.0 { return }
+        let t = (((v.x - p.x) * ex + (v.y - p.y) * ey) / len2).clamp(0.0, 1.0);
+        let (cx, cy) = (p.x + t * ex, p.y + t * ey);
+        let (dx, dy) = (v.x - cx, v.y - cy);
+        let d = (dx * dx + dy * dy).sqrt();
+        if d >= reach || d == 0.0 { return }
+        let (nx, ny) = (dx / d, dy / d);
+        let (wp, wq) = (1.0 - t, t);
+        let (iv, ip, iq) = (v.inverse_mass(), p.inverse_mass(), q.inverse_mass());
+        // a share of the push and impulse goes to each end of the link, by how near and how light it is
+        let share = iv + wp * wp * ip + wq * wq * iq;
+        if share == 0.0 { return }
+        let push = (reach - d) / share;
+        let (mx, my) = (wp * p.momentum_x + wq * q.momentum_x, wp * p.momentum_y + wq * q.momentum_y);
+        let fric = if v.wheel { 0.0 } else { friction };
+        let (jn, jt) = contact_impulse(v.momentum_x - mx, v.momentum_y - my, nx, ny, bounce, fric, share);
+        let (ix, iy) = (jn * nx - jt * ny, jn * ny + jt * nx);
+        let v = &mut self.vertices[i];
+        v.x += iv * push * nx; v.y += iv * push * ny;
+        v.momentum_x += iv * ix; v.momentum_y += iv * iy;
+        for (end, w) in [(src, wp * ip), (dest, wq * iq)] {
+            let e = &mut self.vertices[end];
+            e.x -= w * push * nx; e.y -= w * push * ny;
+            e.momentum_x -= w * ix; e.momentum_y -= w * iy;
+        }
+    }
+}
+
+/// Normal and tangential impulse for two things meeting with relative momentum (`rx`, `ry`)
+/// along normal (`nx`, `ny`). `share` spreads it over the bodies involved.
+fn contact_impulse(rx: f64, ry: f64, nx: f64, ny: f64, bounce: f64, friction: f64, share: f64) -> (f64, f64) {
+    let vn = rx * nx + ry * ny;
+    if vn >= 0.0 { return (0.0, 0.0) }
+    let vt = -rx * ny + ry * nx;
+    (-(1.0 + bounce) * vn / share, -vt * friction / share)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wheels_stack_and_vertices_land_on_links() {
+        let mut world = World::default();
+        world.environment.collisions = Collisions::BetweenBodies;
+        let bottom = world.add_vertex(200.0, 20.0, 0.0, 0.0, 20, 0.0, 0);
+        let top = world.add_vertex(205.0, 150.0, 0.0, 0.0, 20, 0.0, 0);
+        let left = world.add_vertex(400.0, 10.0, 0.0, 0.0, 0, 0.0, 0);
+        let right = world.add_vertex(500.0, 10.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(left, right, 0);
+        let dropped = world.add_vertex(450.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        for _ in 0..300 {
+            world.step();
+        }
+        let (a, b) = (world.vertices[bottom], world.vertices[top]);
+        assert!(((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt() > 39.0);
+        assert!(world.vertices[dropped].y > world.vertices[left].y.min(world.vertices[right].y));
+
+        world.environment.collisions = Collisions::Off;
+        for _ in 0..300 {
+            world.step();
+        }
+        assert!(world.vertices[dropped].y < 5.0);
+    }
+
+    #[test]
+    fn same_body_only_collides_when_asked() {
+        let mut world = World::default();
+        let a = world.add_vertex(100.0, 100.0, 0.0, 0.0, 10, 0.0, 0);
+        let b = world.add_vertex(150.0, 100.0, 0.0, 0.0, 10, 0.0, 0);
+        let c = world.add_vertex(105.0, 100.0, 0.0, 0.0, 10, 0.0, 0);
+        world.add_link(a, b, 0);
+        world.add_link(b, c, 0);
+        world.environment.collisions = Collisions::BetweenBodies;
+        world.collide();
+        assert_eq!(world.vertices[c].x, 105.0);
+        world.environment.collisions = Collisions::All;
+        world.collide();
+        assert!(world.vertices[c].x - world.vertices[a].x >= 19.99);
+    }
+}
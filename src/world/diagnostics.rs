@@ -0,0 +1,91 @@
+//! Measurements for working out why a creature blew up: how much energy is in it and
+//! where, how fast it's going as a whole, and whether anything has gone non-finite.
+//! Energies are in the simulation's own units, with momentum standing in for velocity,
+//! so they're for comparing over time rather than against anything physical.
+use super::{LinkKind, World};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Diagnostics {
+    /// Half of mass times speed squared, summed over the vertices.
+    pub kinetic: f64,
+    /// Mass times gravity times height, summed over the vertices.
+    pub potential: f64,
+    /// Half of tension times how far each link is from its length, squared, for links
+    /// that are pulling or pushing.
+    pub spring: f64,
+    /// Mass-weighted average momentum.
+    pub velocity: (f64, f64),
+    /// The most strained link, by the size of its strain, and that strain.
+    pub max_strain: Option<(usize, f64)>,
+    /// Vertices whose position or momentum is NaN or infinite.
+    pub non_finite: Vec<usize>,
+}
+
+impl World {
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut d = Diagnostics::default();
+        let gravity = self.environment.gravity * 1.5;
+        let mut mass = 0.0;
+        for (i, v) in self.vertices.iter().enumerate().filter(|(_, v)| v.used) {
+            if ![v.x, v.y, v.momentum_x, v.momentum_y].iter().all(|n| n.is_finite()) {
+                d.non_finite.push(i);
+                continue
+            }
+            d.kinetic += 0.5 * v.mass * (v.momentum_x * v.momentum_x + v.momentum_y * v.momentum_y);
+            d.potential += v.mass * gravity * v.y;
+            d.velocity.0 += v.mass * v.momentum_x;
+            d.velocity.1 += v.mass * v.momentum_y;
+            mass += v.mass;
+        }
+        if mass > 0.0 { d.velocity = (d.velocity.0 / mass, d.velocity.1 / mass) }
+        for (i, link) in self.links.iter().enumerate() {
+            let (a, b) = (&self.vertices[link.src], &self.vertices[link.dest]);
+            let actual = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+            if !actual.is_finite() { continue }
+            let target = if self.clock_pause { link.length } else { link.length + link.push };
+            let stretch = actual - target;
+            let slack = match link.kind {
+                LinkKind::Rope => stretch < 0.0,
+                LinkKind::Strut => stretch > 0.0,
+                _ => false,
+            };
+            if !slack { d.spring += 0.5 * link.tension * stretch * stretch }
+            if link.length > 0.0 {
+                let strain = self.link_strain(i);
+                if d.max_strain.map_or(true, |(_, s)| strain.abs() > s.abs()) { d.max_strain = Some((i, strain)) }
+            }
+        }
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falling_trades_potential_for_kinetic_and_nans_are_flagged() {
+        let mut world = World::default();
+        world.environment.atmosphere = 0.0;
+        let a = world.add_vertex(100.0, 400.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(150.0, 400.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        let before = world.diagnostics();
+        assert_eq!((before.kinetic, before.spring), (0.0, 0.0));
+        for _ in 0..10 {
+            world.step();
+        }
+        let after = world.diagnostics();
+        assert!(after.kinetic > 0.0 && after.potential < before.potential);
+        assert!(after.velocity.1 < 0.0 && after.velocity.0 == 0.0);
+        assert!(after.non_finite.is_empty());
+
+        // two vertices on the same spot make the legacy integrator divide by zero
+        world.vertices[b].x = world.vertices[a].x;
+        world.vertices[b].y = world.vertices[a].y;
+        world.vertices[b].momentum_x = world.vertices[a].momentum_x;
+        world.vertices[b].momentum_y = world.vertices[a].momentum_y;
+        world.step();
+        assert_eq!(world.diagnostics().non_finite, vec![a, b]);
+    }
+}
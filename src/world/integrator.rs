@@ -0,0 +1,178 @@
+//! The position-based integrators. Rather than springs nudging momentum the way the
+//! legacy integrator does, these move every vertex first and then pull the links back
+//! towards their lengths directly, taking momentum to be however far each vertex ended
+//! up going. That can't overshoot, so stiff creatures settle instead of exploding. Each
+//! tick can also be split into substeps, which stiffens springs and softens impacts.
+use super::{LinkKind, World};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// The original momentum-nudging integrator, for creatures built for it.
+    Legacy,
+    /// One pass over the links per substep, each pulling on where things were before any of
+    /// them moved, so the result doesn't depend on the order the links were made in.
+    Verlet,
+    /// Several passes per substep, each link pulling on where the last one left things.
+    /// Stiffer and closer to exact for rigid links, at some cost in speed.
+    Pbd,
+}
+
+/// The most substeps a tick may be split into.
+pub const MAX_SUBSTEPS: u32 = 32;
+/// How many passes `Integrator::Pbd` makes over the links each substep.
+const PBD_ITERATIONS: usize = 8;
+
+impl World {
+    /// How readily vertex `i` moves when a link pulls on it: not at all if pinned or held.
+    fn weight(&self, i: usize) -> f64 {
+        if self.held == Some(i) { 0.0 } else { self.vertices[i].inverse_mass() }
+    }
+    pub(super) fn step_positions(&mut self, integrator: Integrator) {
+        let substeps = self.environment.substeps.clamp(1, MAX_SUBSTEPS);
+        let h = 1.0 / substeps as f64;
+        let cycle = self.motor_cycle();
+        for _ in 0..substeps {
+            for i in 0..self.vertices.len() {
+                let fixed = self.held == Some(i);
+                let env = &self.environment;
+                let vertex = &mut self.vertices[i];
+                if !vertex.used { continue }
+                if vertex.just_released { vertex.momentum_x = 0.0; vertex.momentum_y = 0.0; vertex.just_released = false }
+                vertex.momentum_y -= env.gravity * 1.5 * h;
+                vertex.momentum_x += env.left_wind / 10.0 / vertex.mass * h;
+                let drag = (env.atmosphere / vertex.mass).min(1.0) * h;
+                vertex.momentum_x *= 1.0 - drag;
+                vertex.momentum_y *= 1.0 - drag;
+                if fixed || vertex.pinned {
+                    vertex.momentum_x = 0.0;
+                    vertex.momentum_y = 0.0;
+                }
+                vertex.last_x = vertex.x;
+                vertex.last_y = vertex.y;
+                vertex.x += vertex.momentum_x * h;
+                vertex.y += vertex.momentum_y * h;
+            }
+            match integrator {
+                Integrator::Pbd => for _ in 0..PBD_ITERATIONS { self.solve_links(false, PBD_ITERATIONS) },
+                _ => self.solve_links(true, 1),
+            }
+            for vertex in self.vertices.iter_mut().filter(|v| v.used) {
+                vertex.momentum_x = (vertex.x - vertex.last_x) / h;
+                vertex.momentum_y = (vertex.y - vertex.last_y) / h;
+            }
+            self.damp_links(h);
+            for i in 0..self.vertices.len() {
+                if self.vertices[i].used { self.touch_walls(i, h, cycle) }
+            }
+            self.collide_terrain(h);
+            self.collide();
+        }
+    }
+    /// One pass pulling each link towards its length. With `together` every link works from
+    /// the positions at the start of the pass and their moves are averaged at the end;
+    /// otherwise each moves its ends straight away. Stiffness is spread over `passes` so
+    /// that making more passes doesn't make the links any stiffer.
+    fn solve_links(&mut self, together: bool, passes: usize) {
+        let mut moves = vec![(0.0, 0.0, 0u32); if together { self.vertices.len() } else { 0 }];
+        for l in 0..self.links.len() {
+            let link = &self.links[l];
+            let (w1, w2) = (self.weight(link.src), self.weight(link.dest));
+            if w1 + w2 == 0.0 { continue }
+            let (a, b) = (self.vertices[link.src], self.vertices[link.dest]);
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let d = (dx * dx + dy * dy).sqrt();
+            if d == 0.0 { continue }
+            let target = if self.clock_pause { link.length } else { link.length + link.push };
+            let stiffness = match link.kind {
+                LinkKind::Rope if d < target => continue,
+                LinkKind::Strut if d > target => continue,
+                LinkKind::Rigid => 1.0,
+                _ => link.tension.clamp(0.0, 1.0),
+            };
+            let k = 1.0 - (1.0 - stiffness).powf(1.0 / passes as f64);
+            let c = (d - target) / d * k / (w1 + w2);
+            let (fix_x, fix_y) = (dx * c, dy * c);
+            if together {
+                moves[link.src] = (moves[link.src].0 + fix_x * w1, moves[link.src].1 + fix_y * w1, moves[link.src].2 + 1);
+                moves[link.dest] = (moves[link.dest].0 - fix_x * w2, moves[link.dest].1 - fix_y * w2, moves[link.dest].2 + 1);
+            } else {
+                let a = &mut self.vertices[link.src];
+                a.x += fix_x * w1; a.y += fix_y * w1;
+                let b = &mut self.vertices[link.dest];
+                b.x -= fix_x * w2; b.y -= fix_y * w2;
+            }
+        }
+        for (vertex, &(x, y, count)) in self.vertices.iter_mut().zip(&moves) {
+            if count > 0 {
+                vertex.x += x / count as f64;
+                vertex.y += y / count as f64;
+            }
+        }
+    }
+    /// Soaks up some of the speed with which the ends of each damped link part or meet.
+    fn damp_links(&mut self, h: f64) {
+        for l in 0..self.links.len() {
+            let link = &self.links[l];
+            if link.damping == 0.0 { continue }
+            let (w1, w2) = (self.weight(link.src), self.weight(link.dest));
+            if w1 + w2 == 0.0 { continue }
+            let (a, b) = (self.vertices[link.src], self.vertices[link.dest]);
+            let (dx, dy) = (b.x - a.x, b.y - a.y);
+            let d = (dx * dx + dy * dy).sqrt();
+            if d == 0.0 { continue }
+            let (ux, uy) = (dx / d, dy / d);
+            let separating = (b.momentum_x - a.momentum_x) * ux + (b.momentum_y - a.momentum_y) * uy;
+            let amount = separating * (link.damping * h).min(1.0) / (w1 + w2);
+            let a = &mut self.vertices[link.src];
+            a.momentum_x += ux * amount * w1; a.momentum_y += uy * amount * w1;
+            let b = &mut self.vertices[link.dest];
+            b.momentum_x -= ux * amount * w2; b.momentum_y -= uy * amount * w2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Presets;
+
+    /// The walker with its links as stiff as the editor allows.
+    fn stiff_walker(integrator: Integrator) -> World {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        for link in &mut world.links {
+            link.tension = 1.5;
+        }
+        world.environment.integrator = integrator;
+        world.environment.substeps = 4;
+        world
+    }
+
+    #[test]
+    fn position_integrators_keep_stiff_creatures_together() {
+        for &integrator in &[Integrator::Verlet, Integrator::Pbd] {
+            let mut world = stiff_walker(integrator);
+            for _ in 0..1000 {
+                world.step();
+            }
+            for i in 0..world.links.len() {
+                assert!(world.link_strain(i).abs() < 0.5, "{:?} link {} strained {}", integrator, i, world.link_strain(i));
+            }
+        }
+    }
+
+    #[test]
+    fn verlet_does_not_depend_on_link_order() {
+        let mut forwards = stiff_walker(Integrator::Verlet);
+        let mut backwards = stiff_walker(Integrator::Verlet);
+        backwards.links.reverse();
+        for _ in 0..200 {
+            forwards.step();
+            backwards.step();
+        }
+        for (a, b) in forwards.vertices.iter().zip(&backwards.vertices) {
+            assert!((a.x - b.x).abs() < 1e-6 && (a.y - b.y).abs() < 1e-6, "{:?} {:?}", a, b);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Motor wheels. A wheel on its own only spins as fast as whatever it rolls over; a
+//! motor pushes its rim round as well, and wherever the wheel is touching a wall, the
+//! floor or the terrain, that push drives the wheel along the surface.
+use super::World;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MotorKind {
+    Off,
+    /// Pushes the rim round by the same amount every tick.
+    Torque,
+    /// Pushes the rim towards a set speed, but by no more than the torque each tick.
+    Speed,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Motor {
+    pub kind: MotorKind,
+    /// How much the rim's speed changes per tick: always, or at most when aiming for `speed`.
+    pub torque: f64,
+    /// The rim speed a `Speed` motor aims for, in the same units as `Vertex::momentum_c`.
+    pub speed: f64,
+    /// Scale the motor by the muscle cycle, so it drives one way for half the cycle and
+    /// back the other for the rest.
+    pub cycle: bool,
+}
+impl Default for Motor {
+    /// Off, but ready with sensible settings for when it's turned on.
+    fn default() -> Self {
+        Motor { kind: MotorKind::Off, torque: 0.5, speed: 5.0, cycle: false }
+    }
+}
+impl Motor {
+    /// How much to change the rim speed this tick, given how fast it's going now and,
+    /// for motors that follow it, `World::motor_cycle`.
+    pub fn drive(&self, rim_speed: f64, cycle: f64) -> f64 {
+        let scale = if self.cycle { cycle } else { 1.0 };
+        match self.kind {
+            MotorKind::Off => 0.0,
+            MotorKind::Torque => self.torque * scale,
+            MotorKind::Speed => (self.speed * scale - rim_speed).clamp(-self.torque.abs(), self.torque.abs()),
+        }
+    }
+}
+
+impl World {
+    /// Where the muscle cycle has got to, going from 1 down to -1 and back once per cycle.
+    pub(super) fn motor_cycle(&self) -> f64 {
+        (self.cycle_time / self.environment.cycle_length.max(1) as f64 * std::f64::consts::TAU).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn motor_wheels_drive_along_the_floor_and_up_to_speed() {
+        let mut world = World::default();
+        let wheel = world.add_vertex(100.0, 20.0, 0.0, 0.0, 20, 0.0, 0);
+        world.vertices[wheel].motor = Motor { kind: MotorKind::Speed, torque: 0.2, speed: 3.0, cycle: false };
+        for _ in 0..100 {
+            world.step();
+        }
+        let v = world.vertices[wheel];
+        assert!(v.x > 250.0, "{:?}", v);
+        assert!((v.momentum_c - 3.0).abs() < 0.1, "{:?}", v);
+
+        // in the air it only spins
+        let spinner = world.add_vertex(600.0, 300.0, 0.0, 0.0, 20, 0.0, 0);
+        world.vertices[spinner].motor = Motor { kind: MotorKind::Torque, torque: 0.1, speed: 0.0, cycle: false };
+        world.environment.gravity = 0.0;
+        for _ in 0..10 {
+            world.step();
+        }
+        let v = world.vertices[spinner];
+        assert_eq!(v.x, 600.0);
+        assert!((v.momentum_c - 1.0).abs() < 1e-9, "{:?}", v);
+    }
+}
@@ -0,0 +1,291 @@
+//! Muscles: links whose length is pushed out (or pulled in) for a stretch of each cycle.
+//! A muscle is strongest at its `push_timing` and does nothing more than `push_span`
+//! ticks either side of it; its waveform decides how it gets there. Alternatively it can
+//! follow a list of keyframes, for muscles that pulse more than once a cycle or hold still.
+//! Each link's phase puts it in a muscle group, which can run its muscles ahead of the main
+//! cycle, faster than it, or not at all.
+use super::{Link, World};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Waveform {
+    /// Ramps straight up to full strength and straight back down, as muscles always have.
+    Triangle,
+    /// Rises and falls along half a cosine, so it starts and stops gently.
+    Sine,
+    /// Full strength for the whole span, snapping on and off.
+    Square,
+    /// Like the triangle, but easing in and out at either end and at the peak.
+    Smoothstep,
+    /// Builds up steadily across the whole span, then lets go at once.
+    Sawtooth,
+}
+impl Waveform {
+    /// How strongly the muscle pushes, from 0 to 1, at `t` spans from its timing, where
+    /// `t` runs from -1 at the start of the span to 1 at the end.
+    pub fn level(self, t: f64) -> f64 {
+        match self {
+            Waveform::Triangle => 1.0 - t.abs(),
+            Waveform::Sine => 0.5 + 0.5 * (t * std::f64::consts::PI).cos(),
+            Waveform::Square => 1.0,
+            Waveform::Smoothstep => {
+                let s = 1.0 - t.abs();
+                s * s * (3.0 - 2.0 * s)
+            }
+            Waveform::Sawtooth => (1.0 + t) / 2.0,
+        }
+    }
+}
+
+/// A point on a muscle's curve: `time` ticks into the cycle it is pushing by `extension`,
+/// in the same units as `Link::push_strength`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub time: f64,
+    pub extension: f64,
+}
+
+/// The muscles of the links whose `phase` is this group's place in `World::groups`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MuscleGroup {
+    /// Empty for groups that haven't been named.
+    pub name: String,
+    /// How many ticks its muscles run ahead of the main cycle.
+    pub offset: i32,
+    /// How many times its muscles go round for each turn of the main cycle. A whole number,
+    /// since `group_time` wraps with the main cycle and would jump back otherwise.
+    pub speed: u32,
+    /// Its muscles only push while it's on.
+    pub enabled: bool,
+}
+impl Default for MuscleGroup {
+    fn default() -> Self {
+        MuscleGroup { name: String::new(), offset: 0, speed: 1, enabled: true }
+    }
+}
+
+impl Link {
+    /// How far its keyframes have it pushing at `time`, going in a straight line from each
+    /// keyframe to the next and from the last round to the first, a cycle of `cycle_size`
+    /// ticks later. None if it has none.
+    pub fn keyframe_extension(&self, time: f64, cycle_size: f64) -> Option<f64> {
+        let keys = &self.keyframes;
+        let (first, last) = (*keys.first()?, *keys.last()?);
+        let (before, after) = match keys.iter().position(|k| k.time > time) {
+            Some(0) => (Keyframe { time: last.time - cycle_size, ..last }, first),
+            Some(n) => (keys[n - 1], keys[n]),
+            None => (last, Keyframe { time: first.time + cycle_size, ..first }),
+        };
+        if after.time <= before.time { return Some(before.extension) }
+        let t = (time - before.time) / (after.time - before.time);
+        Some(before.extension + (after.extension - before.extension) * t)
+    }
+    /// Puts the keyframes back in order after one has been added or moved.
+    pub fn sort_keyframes(&mut self) {
+        self.keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+    }
+}
+
+impl World {
+    /// Where in the cycle the muscles of links in group `phase` have got to, or None if the
+    /// group is off. Links in groups that haven't been set up follow the main cycle.
+    pub fn group_time(&self, phase: u8) -> Option<f64> {
+        match self.groups.get(phase as usize) {
+            Some(group) if !group.enabled => None,
+            Some(group) if (group.offset, group.speed) != (0, 1) => {
+                Some((self.cycle_time * group.speed as f64 + group.offset as f64).rem_euclid(self.environment.cycle_length.max(1) as f64))
+            }
+            _ => Some(self.cycle_time),
+        }
+    }
+    /// Works out how far each muscle is pushing at this point in the cycle.
+    pub(super) fn update_muscles(&mut self) {
+        let cycle_size = self.environment.cycle_length.max(1) as f64;
+        let soloing = self.links.iter().any(|link| link.solo);
+        let times: Vec<Option<f64>> = (0..self.groups.len()).map(|phase| self.group_time(phase as u8)).collect();
+        for link in &mut self.links {
+            link.push = 0.0;
+            if link.muted || (soloing && !link.solo) { continue }
+            let time = match times.get(link.phase as usize) {
+                Some(None) => continue,
+                Some(&Some(time)) => time,
+                None => self.cycle_time,
+            };
+            if let Some(extension) = link.keyframe_extension(time, cycle_size) {
+                link.push = (extension / 30.0) * link.length;
+                continue
+            }
+            // how many ticks past its timing the cycle is, allowing for spans that wrap
+            // round the end of the cycle
+            let (timing, span) = (link.push_timing as f64, link.push_span as f64);
+            let mut offset = None;
+            if time >= timing - span && time < timing + span {
+                offset = Some(time - timing);
+            }
+            if timing + span > cycle_size && time < timing + span - cycle_size {
+                offset = Some(time - (timing - cycle_size));
+            }
+            if timing - span < 0.0 && time > timing - span + cycle_size {
+                offset = Some(time - (timing + cycle_size));
+            }
+            if let Some(offset) = offset {
+                link.push = link.push_strength * link.waveform.level(offset / span);
+                link.push = (link.push / 30.0) * link.length;
+            }
+        }
+    }
+    /// Changes how many ticks a cycle takes, stretching every muscle's timing, span and
+    /// keyframes, each group's offset and the clock to match, so gaits keep their shape.
+    pub fn set_cycle_length(&mut self, length: i32) {
+        let length = length.max(1);
+        let scale = length as f64 / self.environment.cycle_length.max(1) as f64;
+        let stretch = |ticks: i32| (ticks as f64 * scale).round() as i32;
+        for link in &mut self.links {
+            link.push_timing = stretch(link.push_timing);
+            // a pulse too short to stretch still keeps a tick
+            link.push_span = stretch(link.push_span).max(link.push_span.min(1));
+            for key in &mut link.keyframes {
+                key.time *= scale;
+            }
+        }
+        for group in &mut self.groups {
+            group.offset = stretch(group.offset);
+        }
+        self.cycle_time *= scale;
+        self.environment.cycle_length = length;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waveforms_share_a_span_but_not_a_shape() {
+        let mut world = World::default();
+        let a = world.add_vertex(100.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(130.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        let link = &mut world.links[0];
+        link.push_strength = 10.0;
+        link.push_timing = 190;
+        link.push_span = 20;
+        let push_at = |world: &mut World, waveform, tick| {
+            world.links[0].waveform = waveform;
+            world.cycle_time = tick as f64;
+            world.update_muscles();
+            world.links[0].push
+        };
+        // partway through the span, some wrapping round the end of the cycle
+        for &(waveform, tick, expected) in &[
+            (Waveform::Triangle, 180, 5.0),
+            (Waveform::Triangle, 0, 5.0),
+            (Waveform::Square, 0, 10.0),
+            (Waveform::Sine, 180, 5.0),
+            (Waveform::Smoothstep, 175, 1.5625),
+            (Waveform::Sawtooth, 5, 8.75),
+        ] {
+            let push = push_at(&mut world, waveform, tick);
+            assert!((push - expected).abs() < 1e-9, "{:?} at {}: {}", waveform, tick, push);
+        }
+        for &waveform in &[Waveform::Triangle, Waveform::Sine, Waveform::Square, Waveform::Smoothstep, Waveform::Sawtooth] {
+            assert_eq!(push_at(&mut world, waveform, 100), 0.0);
+        }
+    }
+
+    #[test]
+    fn keyframes_pulse_twice_and_wrap_round_the_cycle() {
+        let mut world = World::default();
+        let a = world.add_vertex(100.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(130.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        let link = &mut world.links[0];
+        link.push_strength = 10.0;
+        link.keyframes = [(20.0, 0.0), (40.0, 10.0), (60.0, 0.0), (120.0, 6.0), (150.0, 6.0), (180.0, 2.0)]
+            .iter().map(|&(time, extension)| Keyframe { time, extension }).collect();
+        for &(tick, expected) in &[(30, 5.0), (40, 10.0), (90, 3.0), (135, 6.0), (190, 1.5), (0, 1.0), (10, 0.5)] {
+            world.cycle_time = tick as f64;
+            world.update_muscles();
+            assert!((world.links[0].push - expected).abs() < 1e-9, "at {}: {}", tick, world.links[0].push);
+        }
+    }
+
+    #[test]
+    fn muted_and_unsoloed_muscles_stay_still() {
+        let mut world = World::default();
+        for i in 0..3 {
+            let a = world.add_vertex(100.0 * i as f64, 100.0, 0.0, 0.0, 0, 0.0, 0);
+            let b = world.add_vertex(100.0 * i as f64 + 30.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+            world.add_link(a, b, 0);
+            world.links[i].push_strength = 10.0;
+            world.links[i].push_timing = 50;
+        }
+        world.cycle_time = 50.0;
+        let pushing = |world: &mut World| {
+            world.update_muscles();
+            world.links.iter().map(|link| link.push != 0.0).collect::<Vec<_>>()
+        };
+        assert_eq!(pushing(&mut world), vec![true, true, true]);
+        world.links[0].muted = true;
+        assert_eq!(pushing(&mut world), vec![false, true, true]);
+        world.links[2].solo = true;
+        assert_eq!(pushing(&mut world), vec![false, false, true]);
+        world.links[0].solo = true;
+        assert_eq!(pushing(&mut world), vec![false, false, true]);
+    }
+
+    #[test]
+    fn groups_shift_speed_up_and_silence_their_muscles() {
+        let mut world = World::default();
+        for i in 0..3 {
+            let a = world.add_vertex(100.0 * i as f64, 100.0, 0.0, 0.0, 0, 0.0, 0);
+            let b = world.add_vertex(100.0 * i as f64 + 30.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+            world.add_link(a, b, i as u8);
+            world.links[i].push_strength = 10.0;
+            world.links[i].push_timing = 100;
+            world.links[i].push_span = 10;
+        }
+        world.groups = vec![
+            MuscleGroup::default(),
+            MuscleGroup { offset: 50, ..MuscleGroup::default() },
+            MuscleGroup { speed: 2, ..MuscleGroup::default() },
+        ];
+        let pushing = |world: &mut World, tick| {
+            world.cycle_time = tick as f64;
+            world.update_muscles();
+            world.links.iter().map(|link| link.push != 0.0).collect::<Vec<_>>()
+        };
+        assert_eq!(pushing(&mut world, 100), vec![true, false, false]);
+        assert_eq!(pushing(&mut world, 50), vec![false, true, true]);
+        assert_eq!(pushing(&mut world, 150), vec![false, false, true]);
+        world.groups[2].enabled = false;
+        assert_eq!(pushing(&mut world, 150), vec![false, false, false]);
+        // links in groups that don't exist yet follow the main cycle
+        world.links[0].phase = 7;
+        assert_eq!(pushing(&mut world, 100), vec![true, false, false]);
+    }
+
+    #[test]
+    fn changing_the_cycle_length_stretches_the_muscles_to_fit() {
+        let mut world = World::default();
+        let a = world.add_vertex(100.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        let b = world.add_vertex(130.0, 100.0, 0.0, 0.0, 0, 0.0, 0);
+        world.add_link(a, b, 0);
+        world.links[0].push_strength = 10.0;
+        world.links[0].push_timing = 100;
+        world.links[0].push_span = 10;
+        world.groups = vec![MuscleGroup::default(), MuscleGroup { offset: 40, ..MuscleGroup::default() }];
+        world.cycle_time = 60.0;
+        world.set_cycle_length(50);
+        assert_eq!((world.links[0].push_timing, world.links[0].push_span), (25, 3));
+        assert_eq!((world.groups[1].offset, world.cycle_time), (10, 15.0));
+        // the pulse sits at the same point in the shorter cycle, and the clock wraps sooner
+        world.cycle_time = 25.0;
+        world.update_muscles();
+        assert_eq!(world.links[0].push, 10.0 / 30.0 * world.links[0].length);
+        world.environment.clock_speed = 0.5;
+        world.cycle_time = 49.75;
+        world.step();
+        assert_eq!(world.cycle_time, 0.25);
+    }
+}
@@ -0,0 +1,21 @@
+//! The creatures that come with Botz, as the .botz files the original saved them in.
+
+#[derive(Debug, Copy, Clone)]
+pub enum Presets {
+    Walker, Unicycle, Jumper, Spikeball, Muscles, Dancer, AntiGrav, Blank
+}
+impl Presets {
+    pub const ALL: [Presets; 8] = [Presets::Walker, Presets::Unicycle, Presets::Jumper, Presets::Spikeball, Presets::Muscles, Presets::Dancer, Presets::AntiGrav, Presets::Blank];
+    pub fn source(self) -> &'static str {
+        match self {
+            Presets::Blank => include_str!("../../blank.botz"),
+            Presets::Walker => include_str!("../../walker.botz"),
+            Presets::Unicycle => include_str!("../../unicycle.botz"),
+            Presets::Jumper => include_str!("../../jumper.botz"),
+            Presets::Spikeball => include_str!("../../spikeball.botz"),
+            Presets::Muscles => include_str!("../../muscles.botz"),
+            Presets::Dancer => include_str!("../../dancer.botz"),
+            Presets::AntiGrav => include_str!("../../antigrav.botz"),
+        }
+    }
+}
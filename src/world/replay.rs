@@ -0,0 +1,340 @@
+//! Recording runs so they can be played back exactly. A recording is the state of the
+//! world when it started plus every change made to it from outside the physics, each
+//! stamped with the tick it happened before: dragging or letting go of a vertex, moving
+//! an environment slider, resizing the window. Nothing needs to know which of those it
+//! was; before each tick the recorder compares the world with how the last tick left
+//! it, and whatever differs is logged. Playing the log back through `World::step`
+//! reproduces the run bit for bit on the same build.
+use super::{Environment, Link, MuscleGroup, Obstacle, Vertex, Walls, World};
+use serde::{Deserialize, Serialize};
+
+/// Bump this whenever the layout of `Replay` or anything it contains changes. Replays
+/// are only exact on the build that recorded them, so older ones are not migrated.
+pub const REPLAY_VERSION: u32 = 16;
+
+/// Everything `World::step` reads or writes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    environment: Environment,
+    walls: Walls,
+    vertices: Vec<Vertex>,
+    links: Vec<Link>,
+    terrain: Vec<Obstacle>,
+    groups: Vec<MuscleGroup>,
+    cycle_time: f64,
+    clock_pause: bool,
+    auto_reverse_cycle: i32,
+    auto_reverse_enabled: bool,
+    rightwall: f64,
+    ceiling: f64,
+    held: Option<usize>,
+}
+
+/// A change made to the world between two ticks by something other than the physics.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Change {
+    Environment(Environment),
+    Walls(Walls),
+    Vertex(usize, Vertex),
+    Vertices(Vec<Vertex>),
+    Link(usize, Link),
+    Links(Vec<Link>),
+    Terrain(Vec<Obstacle>),
+    Groups(Vec<MuscleGroup>),
+    Clock { cycle_time: f64, paused: bool, auto_reverse_cycle: i32, auto_reverse_enabled: bool },
+    Bounds { rightwall: f64, ceiling: f64 },
+    Held(Option<usize>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub tick: u64,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub start: Snapshot,
+    pub events: Vec<Event>,
+    pub ticks: u64,
+    /// `Snapshot::fingerprint` of the world after the last tick, to check playback against.
+    pub fingerprint: u64,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Json(serde_json::Error),
+    UnsupportedVersion(u32),
+    /// A link in the starting state joins a vertex that isn't there.
+    DanglingLink(usize),
+    /// A change logged before this tick points at a vertex or link that isn't there.
+    BadEvent(u64),
+}
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Json(e) => write!(f, "{}", e),
+            ReplayError::UnsupportedVersion(v) => write!(f, "replay version {} is not supported (this build reads up to {})", v, REPLAY_VERSION),
+            ReplayError::DanglingLink(i) => write!(f, "link {} refers to a vertex that does not exist", i),
+            ReplayError::BadEvent(tick) => write!(f, "a change at tick {} refers to a vertex or link that does not exist", tick),
+        }
+    }
+}
+impl std::error::Error for ReplayError {}
+
+impl Snapshot {
+    /// Selection belongs to the editor and doesn't affect the physics, so it's left out
+    /// rather than showing up as a change every frame.
+    fn of(world: &World) -> Snapshot {
+        Snapshot {
+            environment: world.environment.clone(),
+            walls: world.walls.clone(),
+            vertices: world.vertices.iter().map(|v| Vertex { selected: false, ..*v }).collect(),
+            links: world.links.clone(),
+            terrain: world.terrain.clone(),
+            groups: world.groups.clone(),
+            cycle_time: world.cycle_time,
+            clock_pause: world.clock_pause,
+            auto_reverse_cycle: world.auto_reverse_cycle,
+            auto_reverse_enabled: world.auto_reverse_enabled,
+            rightwall: world.rightwall,
+            ceiling: world.ceiling,
+            held: world.held,
+        }
+    }
+    fn restore(&self, world: &mut World) {
+        world.environment = self.environment.clone();
+        world.walls = self.walls.clone();
+        world.replace_bodies(self.vertices.clone(), self.links.clone());
+        world.terrain = self.terrain.clone();
+        world.groups = self.groups.clone();
+        world.cycle_time = self.cycle_time;
+        world.clock_pause = self.clock_pause;
+        world.auto_reverse_cycle = self.auto_reverse_cycle;
+        world.auto_reverse_enabled = self.auto_reverse_enabled;
+        world.rightwall = self.rightwall;
+        world.ceiling = self.ceiling;
+        world.held = self.held;
+    }
+    /// A hash of the exact bits of the state. Unlike comparing with `==` this tells
+    /// `0.0` from `-0.0`, and still works once a run has blown up into NaNs.
+    pub fn fingerprint(&self) -> u64 {
+        // FNV-1a over the debug text, which prints every float exactly
+        format!("{:?}", self).bytes().fold(0xcbf29ce484222325, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+    }
+    /// The changes that turn `self` into `to`.
+    fn changes(&self, to: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.environment != to.environment { changes.push(Change::Environment(to.environment.clone())) }
+        if self.walls != to.walls { changes.push(Change::Walls(to.walls.clone())) }
+        if self.vertices.len() != to.vertices.len() {
+            changes.push(Change::Vertices(to.vertices.clone()));
+        } else {
+            for (i, (a, b)) in self.vertices.iter().zip(&to.vertices).enumerate() {
+                if a != b { changes.push(Change::Vertex(i, *b)) }
+            }
+        }
+        if self.links.len() != to.links.len() {
+            changes.push(Change::Links(to.links.clone()));
+        } else {
+            for (i, (a, b)) in self.links.iter().zip(&to.links).enumerate() {
+                if a != b { changes.push(Change::Link(i, b.clone())) }
+            }
+        }
+        if self.terrain != to.terrain { changes.push(Change::Terrain(to.terrain.clone())) }
+        if self.groups != to.groups { changes.push(Change::Groups(to.groups.clone())) }
+        if (self.cycle_time, self.clock_pause, self.auto_reverse_cycle, self.auto_reverse_enabled) != (to.cycle_time, to.clock_pause, to.auto_reverse_cycle, to.auto_reverse_enabled) {
+            changes.push(Change::Clock { cycle_time: to.cycle_time, paused: to.clock_pause, auto_reverse_cycle: to.auto_reverse_cycle, auto_reverse_enabled: to.auto_reverse_enabled });
+        }
+        if (self.rightwall, self.ceiling) != (to.rightwall, to.ceiling) { changes.push(Change::Bounds { rightwall: to.rightwall, ceiling: to.ceiling }) }
+        if self.held != to.held { changes.push(Change::Held(to.held)) }
+        changes
+    }
+}
+
+impl Change {
+    fn apply(&self, world: &mut World) {
+        match self {
+            Change::Environment(e) => world.environment = e.clone(),
+            Change::Walls(w) => world.walls = w.clone(),
+            Change::Vertex(i, v) => world.vertices[*i] = *v,
+            Change::Vertices(v) => { world.vertices = v.clone(); world.triangles_updated = true }
+            Change::Link(i, l) => world.links[*i] = l.clone(),
+            Change::Links(l) => { world.links = l.clone(); world.triangles_updated = true }
+            Change::Terrain(t) => world.terrain = t.clone(),
+            Change::Groups(g) => world.groups = g.clone(),
+            Change::Clock { cycle_time, paused, auto_reverse_cycle, auto_reverse_enabled } => {
+                world.cycle_time = *cycle_time;
+                world.clock_pause = *paused;
+                world.auto_reverse_cycle = *auto_reverse_cycle;
+                world.auto_reverse_enabled = *auto_reverse_enabled;
+            }
+            Change::Bounds { rightwall, ceiling } => { world.rightwall = *rightwall; world.ceiling = *ceiling }
+            Change::Held(h) => world.held = *h,
+        }
+    }
+}
+
+/// Steps a world while logging everything done to it in between.
+pub struct Recorder {
+    start: Snapshot,
+    events: Vec<Event>,
+    ticks: u64,
+    last: Snapshot,
+}
+impl Recorder {
+    pub fn start(world: &World) -> Recorder {
+        let start = Snapshot::of(world);
+        Recorder { last: start.clone(), start, events: Vec::new(), ticks: 0 }
+    }
+    /// Use in place of `World::step` while recording.
+    pub fn step(&mut self, world: &mut World) {
+        let now = Snapshot::of(world);
+        let tick = self.ticks;
+        self.events.extend(self.last.changes(&now).into_iter().map(|change| Event { tick, change }));
+        world.step();
+        self.ticks += 1;
+        self.last = Snapshot::of(world);
+    }
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+    pub fn finish(self) -> Replay {
+        Replay { version: REPLAY_VERSION, start: self.start, events: self.events, ticks: self.ticks, fingerprint: self.last.fingerprint() }
+    }
+}
+
+/// Steps a world through a recording one tick at a time.
+pub struct Player {
+    replay: Replay,
+    tick: u64,
+    next_event: usize,
+}
+impl Player {
+    /// Puts the world back how it was when the recording started.
+    pub fn start(replay: Replay, world: &mut World) -> Player {
+        replay.start.restore(world);
+        Player { replay, tick: 0, next_event: 0 }
+    }
+    /// Runs the next recorded tick, returning false once there are none left.
+    pub fn step(&mut self, world: &mut World) -> bool {
+        if self.tick >= self.replay.ticks { return false }
+        while let Some(event) = self.replay.events.get(self.next_event).filter(|e| e.tick == self.tick) {
+            event.change.apply(world);
+            self.next_event += 1;
+        }
+        world.step();
+        self.tick += 1;
+        true
+    }
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+    /// Whether a finished playback ended somewhere other than the recording did.
+    pub fn diverged(&self, world: &World) -> bool {
+        Snapshot::of(world).fingerprint() != self.replay.fingerprint
+    }
+}
+
+impl Replay {
+    /// Plays the whole recording into `world` and checks it ended up exactly where it did when recorded.
+    pub fn verify(&self, world: &mut World) -> bool {
+        let mut player = Player::start(self.clone(), world);
+        while player.step(world) {}
+        !player.diverged(world)
+    }
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("replays always serialize")
+    }
+    pub fn from_json(text: &str) -> Result<Replay, ReplayError> {
+        let value: serde_json::Value = serde_json::from_str(text).map_err(ReplayError::Json)?;
+        let replay: Replay = match value.get("version").and_then(|v| v.as_u64()) {
+            Some(v) if v == REPLAY_VERSION as u64 => serde_json::from_value(value).map_err(ReplayError::Json)?,
+            v => return Err(ReplayError::UnsupportedVersion(v.unwrap_or(0) as u32)),
+        };
+        replay.check()?;
+        Ok(replay)
+    }
+    /// Makes sure playback never indexes past the end of the vertices or links, following
+    /// the counts through every change that replaces them.
+    fn check(&self) -> Result<(), ReplayError> {
+        let dangling = |links: &[Link], vertices: usize| links.iter().position(|l| l.src >= vertices || l.dest >= vertices);
+        let (mut vertices, mut links) = (self.start.vertices.len(), self.start.links.len());
+        if let Some(i) = dangling(&self.start.links, vertices) { return Err(ReplayError::DanglingLink(i)) }
+        for event in &self.events {
+            let ok = match &event.change {
+                Change::Vertex(i, _) => *i < vertices,
+                Change::Vertices(v) => { vertices = v.len(); true }
+                Change::Link(i, l) => *i < links && dangling(std::slice::from_ref(l), vertices).is_none(),
+                Change::Links(l) => { links = l.len(); dangling(l, vertices).is_none() }
+                _ => true,
+            };
+            if !ok { return Err(ReplayError::BadEvent(event.tick)) }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::Presets;
+
+    #[test]
+    fn replay_reproduces_interactive_run() {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        let mut recorder = Recorder::start(&world);
+        for tick in 0..600 {
+            // drag vertex 3 about for a while, let go, and fiddle with the sliders
+            if (100..200).contains(&tick) {
+                world.held = Some(3);
+                world.vertices[3].x = 300.0 + (tick as f64 * 0.37).sin() * 40.0;
+                world.vertices[3].y = 150.0;
+            }
+            if tick == 200 { world.held = None; world.vertices[3].just_released = true }
+            if tick == 300 { world.environment.gravity = 0.1; world.environment.left_wind = -3.3 }
+            if tick == 400 { world.rightwall = 500.0 }
+            if tick % 7 == 0 { world.vertices[0].selected = !world.vertices[0].selected }
+            recorder.step(&mut world);
+        }
+        let replay = recorder.finish();
+        assert_eq!(replay.ticks, 600);
+        assert!(replay.events.len() < 150, "{} events", replay.events.len());
+
+        let loaded = Replay::from_json(&replay.to_json()).unwrap();
+        let mut other = World::default();
+        assert!(loaded.verify(&mut other));
+        assert_eq!(Snapshot::of(&other), Snapshot::of(&world));
+    }
+
+    #[test]
+    fn replay_rejects_changes_past_the_end() {
+        let mut world = World::default();
+        world.legacy_parse(Presets::Walker.source()).unwrap();
+        let mut recorder = Recorder::start(&world);
+        world.vertices[1].x += 10.0;
+        recorder.step(&mut world);
+        world.links[1].length += 10.0;
+        recorder.step(&mut world);
+        let replay = recorder.finish();
+        assert!(Replay::from_json(&replay.to_json()).is_ok());
+
+        let vertices = world.vertices.len();
+        let mut bad = replay.clone();
+        bad.events[0].change = Change::Vertex(vertices, world.vertices[1]);
+        assert!(matches!(Replay::from_json(&bad.to_json()), Err(ReplayError::BadEvent(0))));
+
+        let mut bad = replay.clone();
+        bad.events[1].change = Change::Link(1, Link { dest: vertices, ..world.links[1].clone() });
+        assert!(matches!(Replay::from_json(&bad.to_json()), Err(ReplayError::BadEvent(1))));
+
+        let mut bad = replay;
+        bad.start.links[2].src = vertices + 5;
+        assert!(matches!(Replay::from_json(&bad.to_json()), Err(ReplayError::DanglingLink(2))));
+    }
+}
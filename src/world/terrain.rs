@@ -0,0 +1,135 @@
+//! Static scenery for creatures to walk over: ramps, steps, boxes or any other chain
+//! of line segments. Obstacles never move; vertices bounce off them by the same
+//! `wall_bounce` and `wall_friction` rules as the walls.
+use super::World;
+use serde::{Deserialize, Serialize};
+
+/// A chain of line segments, joined back to the start if `closed`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub points: Vec<(f64, f64)>,
+    pub closed: bool,
+}
+
+/// How far off the surface plain vertices are kept, so they stay on the side they landed on.
+const SKIN: f64 = 0.01;
+
+impl Obstacle {
+    pub fn line(x0: f64, y0: f64, x1: f64, y1: f64) -> Obstacle {
+        Obstacle { points: vec![(x0, y0), (x1, y1)], closed: false }
+    }
+    /// A slope rising from the floor at `x` to `height` over `width`, with a vertical back.
+    pub fn ramp(x: f64, width: f64, height: f64) -> Obstacle {
+        Obstacle { points: vec![(x, 0.0), (x + width, height), (x + width, 0.0)], closed: false }
+    }
+    /// A staircase going up from the floor at `x`.
+    pub fn steps(x: f64, count: usize, tread: f64, rise: f64) -> Obstacle {
+        let mut points = vec![(x, 0.0)];
+        for i in 0..count {
+            let (sx, sy) = (x + i as f64 * tread, (i + 1) as f64 * rise);
+            points.push((sx, sy));
+            points.push((sx + tread, sy));
+        }
+        points.push((x + count as f64 * tread, 0.0));
+        Obstacle { points, closed: false }
+    }
+    pub fn rectangle(x: f64, y: f64, width: f64, height: f64) -> Obstacle {
+        Obstacle { points: vec![(x, y), (x + width, y), (x + width, y + height), (x, y + height)], closed: true }
+    }
+    pub fn segments(&self) -> impl Iterator<Item = ((f64, f64), (f64, f64))> + '_ {
+        let wrap = if self.closed && self.points.len() > 2 { self.points.first() } else { None };
+        self.points.windows(2).map(|w| (w[0], w[1])).chain(wrap.map(|&first| (self.points[self.points.len() - 1], first)))
+    }
+}
+
+impl World {
+    /// Keeps each vertex on the side of every obstacle segment it started the tick on.
+    /// `h` is the fraction of a tick being simulated.
+    pub(super) fn collide_terrain(&mut self, h: f64) {
+        if self.terrain.is_empty() { return }
+        let bounce = self.environment.wall_bounce;
+        let cycle = self.motor_cycle();
+        for (i, v) in self.vertices.iter_mut().enumerate() {
+            if !v.used || v.pinned || self.held == Some(i) { continue }
+            let fric = if v.wheel { 0.0 } else { self.environment.wall_friction };
+            for segment in self.terrain.iter().flat_map(|o| o.segments()) {
+                segment_contact(v, segment, bounce, fric, cycle, h);
+            }
+        }
+    }
+}
+
+fn segment_contact(v: &mut super::Vertex, ((x0, y0), (x1, y1)): ((f64, f64), (f64, f64)), bounce: f64, fric: f64, cycle: f64, h: f64) {
+    let r = v.radius as f64;
+    let (ex, ey) = (x1 - x0, y1 - y0);
+    let len2 = ex * ex + ey * ey;
+    if len2 == 0.0 { return }
+    let t = (((v.x - x0) * ex + (v.y - y0) * ey) / len2).clamp(0.0, 1.0);
+    let (cx, cy) = (x0 + t * ex, y0 + t * ey);
+    let (dx, dy) = (v.x - cx, v.y - cy);
+    let d = (dx * dx + dy * dy).sqrt();
+    // which side of the line it was on before this tick's move, and now
+    let len = len2.sqrt();
+    let (lx, ly) = (-ey / len, ex / len);
+    let before = (v.last_x - x0) * lx + (v.last_y - y0) * ly;
+    let after = (v.x - x0) * lx + (v.y - y0) * ly;
+    let crossed = before * after < 0.0 && {
+        let u = before / (before - after);
+        let (px, py) = (v.last_x + u * (v.x - v.last_x), v.last_y + u * (v.y - v.last_y));
+        let along = ((px - x0) * ex + (py - y0) * ey) / len2;
+        (0.0..=1.0).contains(&along)
+    };
+    let (nx, ny) = if crossed || d == 0.0 {
+        let side = if before != 0.0 { before.signum() } else if after != 0.0 { after.signum() } else { 1.0 };
+        (lx * side, ly * side)
+    } else if d < r + 0.1 {
+        (dx / d, dy / d)
+    } else {
+        return
+    };
+    // back out to the surface, on the side it came from
+    v.x = cx + nx * r.max(SKIN);
+    v.y = cy + ny * r.max(SKIN);
+    let (tx, ty) = (ny, -nx);
+    let normal = v.momentum_x * nx + v.momentum_y * ny;
+    let tangent = (v.momentum_x * tx + v.momentum_y * ty) * (1.0 - fric * h);
+    // a motor wheel drives itself along the surface
+    let tangent = if v.wheel { tangent + v.motor.drive(tangent, cycle) * h } else { tangent };
+    let normal = if normal < 0.0 { -normal * bounce } else { normal };
+    v.momentum_x = nx * normal + tx * tangent;
+    v.momentum_y = ny * normal + ty * tangent;
+    if v.wheel { v.momentum_c = tangent }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertices_rest_on_steps_and_slide_down_ramps() {
+        let mut world = World::default();
+        world.terrain.push(Obstacle::steps(100.0, 3, 40.0, 20.0));
+        world.terrain.push(Obstacle::ramp(400.0, 200.0, 100.0));
+        let on_step = world.add_vertex(150.0, 200.0, 0.0, 0.0, 0, 0.0, 0);
+        let on_ramp = world.add_vertex(580.0, 150.0, 0.0, 0.0, 0, 0.0, 0);
+        let fast = world.add_vertex(190.0, 200.0, 0.0, -50.0, 0, 0.0, 0);
+        world.environment.wall_friction = 0.0;
+        world.environment.wall_bounce = 0.0;
+        for _ in 0..30 {
+            world.step();
+        }
+        let v = world.vertices[on_step];
+        assert!((v.x - 150.0).abs() < 1e-6 && (v.y - 40.0).abs() < 0.1, "{:?}", v);
+        assert!(world.vertices[fast].y >= 59.9, "{:?}", world.vertices[fast]);
+        let v = world.vertices[on_ramp];
+        assert!(v.x > 400.0 && v.x < 575.0 && (v.y - (v.x - 400.0) / 2.0).abs() < 0.1, "{:?}", v);
+    }
+
+    #[test]
+    fn closed_obstacles_wrap_around() {
+        let square = Obstacle::rectangle(0.0, 0.0, 10.0, 10.0);
+        assert_eq!(square.segments().count(), 4);
+        assert_eq!(square.segments().last(), Some(((0.0, 10.0), (0.0, 0.0))));
+        assert_eq!(Obstacle::ramp(0.0, 10.0, 5.0).segments().count(), 2);
+    }
+}